@@ -0,0 +1,81 @@
+-- Recipes and their lists live in normalized tables; list order is kept in `position`.
+CREATE TABLE recipes (
+    pk INTEGER PRIMARY KEY AUTOINCREMENT,
+    object_id TEXT NOT NULL UNIQUE,
+    id TEXT NOT NULL UNIQUE,
+    title TEXT NOT NULL,
+    summary TEXT NOT NULL,
+    image TEXT NOT NULL,
+    vegetarian BOOLEAN NOT NULL,
+    vegan BOOLEAN NOT NULL,
+    gluten_free BOOLEAN NOT NULL,
+    dairy_free BOOLEAN NOT NULL,
+    ready_in_minutes INTEGER NOT NULL,
+    servings INTEGER NOT NULL,
+    calories REAL,
+    health_score REAL,
+    fingerprint TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    source TEXT NOT NULL
+);
+
+CREATE INDEX recipes_ready_in_minutes ON recipes (ready_in_minutes);
+CREATE INDEX recipes_servings ON recipes (servings);
+CREATE INDEX recipes_calories ON recipes (calories);
+CREATE INDEX recipes_health_score ON recipes (health_score);
+CREATE INDEX recipes_title ON recipes (title);
+CREATE INDEX recipes_fingerprint ON recipes (fingerprint);
+
+CREATE TABLE recipe_ingredients (
+    recipe_pk INTEGER NOT NULL REFERENCES recipes (pk) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    amount REAL NOT NULL,
+    unit TEXT NOT NULL,
+    PRIMARY KEY (recipe_pk, position)
+);
+
+CREATE INDEX recipe_ingredients_name ON recipe_ingredients (name);
+
+CREATE TABLE recipe_nutrients (
+    recipe_pk INTEGER NOT NULL REFERENCES recipes (pk) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    amount REAL NOT NULL,
+    unit TEXT NOT NULL,
+    PRIMARY KEY (recipe_pk, position)
+);
+
+CREATE INDEX recipe_nutrients_name_amount ON recipe_nutrients (name, amount);
+
+CREATE TABLE recipe_properties (
+    recipe_pk INTEGER NOT NULL REFERENCES recipes (pk) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    amount REAL NOT NULL,
+    PRIMARY KEY (recipe_pk, position)
+);
+
+CREATE INDEX recipe_properties_name_amount ON recipe_properties (name, amount);
+
+CREATE TABLE recipe_steps (
+    recipe_pk INTEGER NOT NULL REFERENCES recipes (pk) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    number INTEGER NOT NULL,
+    step TEXT NOT NULL,
+    PRIMARY KEY (recipe_pk, position)
+);
+
+-- Cuisines, dish types, diets and allergens, told apart by `kind`.
+CREATE TABLE recipe_tags (
+    recipe_pk INTEGER NOT NULL REFERENCES recipes (pk) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (recipe_pk, kind, position)
+);
+
+CREATE INDEX recipe_tags_kind_value ON recipe_tags (kind, value);
+
+-- Mirrors the MongoDB text index; rows are kept in step with `recipes` by the application, keyed by `pk`.
+CREATE VIRTUAL TABLE recipe_search USING fts5 (title, summary, ingredients, instructions, tokenize = 'porter unicode61');
@@ -0,0 +1,10 @@
+-- Keys are stored by hash only; `scopes` holds the JSON array of scope names.
+CREATE TABLE api_keys (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT,
+    revoked BOOLEAN NOT NULL
+);
@@ -0,0 +1,117 @@
+use std::str::FromStr;
+use serde::{ Serialize, Deserialize };
+
+use crate::{ingredients::normalize, models::Recipe};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Intolerance {
+    Dairy,
+    Egg,
+    Gluten,
+    Wheat,
+    Peanut,
+    TreeNut,
+    Shellfish,
+    Fish,
+    Soy,
+    Sesame
+}
+
+impl Intolerance {
+    pub const ALL: [Intolerance; 10] = [
+        Intolerance::Dairy,
+        Intolerance::Egg,
+        Intolerance::Gluten,
+        Intolerance::Wheat,
+        Intolerance::Peanut,
+        Intolerance::TreeNut,
+        Intolerance::Shellfish,
+        Intolerance::Fish,
+        Intolerance::Soy,
+        Intolerance::Sesame
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Intolerance::Dairy => "dairy",
+            Intolerance::Egg => "egg",
+            Intolerance::Gluten => "gluten",
+            Intolerance::Wheat => "wheat",
+            Intolerance::Peanut => "peanut",
+            Intolerance::TreeNut => "tree_nut",
+            Intolerance::Shellfish => "shellfish",
+            Intolerance::Fish => "fish",
+            Intolerance::Soy => "soy",
+            Intolerance::Sesame => "sesame"
+        }
+    }
+
+    // Normalized ingredient words that give the allergen away.
+    fn keywords(&self) -> &'static [&'static str] {
+        match self {
+            Intolerance::Dairy => &["milk", "cheese", "butter", "cream", "yogurt", "yoghurt", "whey", "ghee", "parmesan", "mozzarella", "cheddar", "ricotta", "mascarpone", "feta", "paneer", "buttermilk", "custard"],
+            Intolerance::Egg => &["egg", "mayonnaise", "mayo", "meringue", "aioli"],
+            Intolerance::Gluten => &["flour", "bread", "pasta", "spaghetti", "noodle", "barley", "rye", "couscous", "semolina", "breadcrumb", "seitan", "cracker", "tortilla", "wheat", "malt", "beer"],
+            Intolerance::Wheat => &["wheat", "flour", "bread", "pasta", "spaghetti", "couscous", "semolina", "breadcrumb", "seitan", "tortilla"],
+            Intolerance::Peanut => &["peanut"],
+            Intolerance::TreeNut => &["almond", "cashew", "walnut", "pecan", "pistachio", "hazelnut", "macadamia", "brazil nut", "pine nut"],
+            Intolerance::Shellfish => &["shrimp", "crab", "lobster", "crayfish", "scallop", "clam", "mussel", "oyster"],
+            Intolerance::Fish => &["fish", "salmon", "tuna", "cod", "anchovy", "sardine", "trout", "haddock", "mackerel", "tilapia"],
+            Intolerance::Soy => &["soy", "soya", "tofu", "tempeh", "edamame", "miso"],
+            Intolerance::Sesame => &["sesame", "tahini"]
+        }
+    }
+
+    // Ingredients that contain a keyword but not the allergen.
+    fn exceptions(&self) -> &'static [&'static str] {
+        match self {
+            Intolerance::Dairy => &["peanut butter", "almond butter", "cashew butter", "cocoa butter", "apple butter", "coconut milk", "coconut cream", "almond milk", "soy milk", "oat milk", "rice milk", "cream of tartar"],
+            Intolerance::Egg => &["eggplant"],
+            Intolerance::Gluten | Intolerance::Wheat => &["rice flour", "almond flour", "coconut flour", "corn flour", "cornflour", "rice noodle", "gluten free", "buckwheat"],
+            _ => &[]
+        }
+    }
+
+    fn found_in(&self, ingredient: &str) -> bool {
+        let padded = format!(" {} ", ingredient);
+        let contains = |phrase: &&str| padded.contains(&format!(" {} ", phrase));
+
+        self.keywords().iter().any(contains) && !self.exceptions().iter().any(contains)
+    }
+}
+
+impl FromStr for Intolerance {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_lowercase().replace([' ', '-'], "_");
+
+        Intolerance::ALL.iter()
+            .find(|intolerance| intolerance.name() == value)
+            .copied()
+            .ok_or_else(|| format!(
+                "unknown intolerance '{}', expected one of: {}",
+                value,
+                Intolerance::ALL.iter().map(|intolerance| intolerance.name()).collect::<Vec<_>>().join(", ")
+            ))
+    }
+}
+
+// A recipe contains an allergen if its flags say so or any ingredient matches the dictionary.
+pub fn detect(recipe: &Recipe) -> Vec<Intolerance> {
+    let ingredients: Vec<String> = recipe.ingredients.iter().map(|ingredient| normalize(&ingredient.name)).collect();
+
+    Intolerance::ALL.iter()
+        .filter(|intolerance| {
+            let flagged = match intolerance {
+                Intolerance::Dairy => !recipe.dairy_free,
+                Intolerance::Gluten => !recipe.gluten_free,
+                _ => false
+            };
+
+            flagged || ingredients.iter().any(|ingredient| intolerance.found_in(ingredient))
+        })
+        .copied()
+        .collect()
+}
@@ -1,223 +1,716 @@
+use std::sync::Arc;
 use actix_web::{
+    http::header::{ETag, LINK, LOCATION},
+    middleware::DefaultHeaders,
     web,
-    HttpResponse,
-    Responder,
-    HttpRequest
+    HttpMessage,
+    HttpRequest,
+    HttpResponse
 };
-use mongodb::Database;
+use chrono::{DateTime, Utc};
 use serde::{ Serialize, Deserialize };
 
-use crate::{db::{self, create_recipe, delete_recipe, filter_recipes, read_recipe, update_recipe}, models::{Filters, Recipe}};
+use crate::{auth::{ApiKeyStore, RequireScope, Scope}, bulk::{self, BulkPayload, MAX_BULK_OPERATIONS}, errors::{self, AppError}, filter_builder::validate_filters, sort::parse_sort, pagination::{Cursor, CursorCodec, Direction}, etag::{entity_tag, expected_versions, not_modified}, ingredients::{self, PantryMatch}, models::{Filters, Recipe}, patch::RecipePatch, repository::{KeysetPage, Page, RecipeError, RecipeRepository}, validation::{validate_recipe, validate_replacement}};
+
+const DEFAULT_PAGE_SIZE: usize = 15;
+const MAX_PAGE_SIZE: usize = 100;
+// Keeps the offset, `(page - 1) * page_size`, far from overflowing or from what any backend can skip.
+const MAX_PAGE: usize = u32::MAX as usize / MAX_PAGE_SIZE;
 
 #[derive(Deserialize)]
 pub struct MultipleQueryParams {
+    #[serde(default, alias = "limit")]
+    page_size: Option<usize>,
+    #[serde(default)]
+    page: Option<usize>,
+    #[serde(default)]
+    cursor: Option<String>,
     #[serde(default)]
-    limit: Option<usize>,
+    pagination: Option<String>,
     #[serde(default)]
-    page: Option<usize>
+    sort: Option<String>
 }
 #[derive(Deserialize)]
 pub struct SingleQueryParams {
     #[serde(default)]
-    id: Option<i64>
+    id: Option<String>
 }
 #[derive(Deserialize)]
 pub struct Payload {
     recipe: Recipe
 }
+#[derive(Deserialize)]
+pub struct CreateParams {
+    #[serde(default)]
+    allow_duplicate: bool
+}
+
+// Candidates come back pre-ranked by how few of their ingredients the pantry leaves unmatched, so the
+// cut drops the least promising ones rather than an arbitrary slice.
+const MAX_PANTRY_CANDIDATES: usize = 500;
+
+#[derive(Deserialize)]
+pub struct PantryPayload {
+    have: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    limit: Option<usize>
+}
+
+#[derive(Serialize)]
+pub struct PantryResult {
+    results: Vec<PantryMatch>
+}
+
+#[derive(Deserialize)]
+pub struct KeyPayload {
+    name: String,
+    #[serde(default = "default_scopes")]
+    scopes: Vec<Scope>,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>
+}
+
+fn default_scopes() -> Vec<Scope> {
+    vec![Scope::RecipesRead]
+}
+
+#[derive(Serialize)]
+pub struct CreatedKey {
+    id: String,
+    name: String,
+    key: String,
+    scopes: Vec<Scope>,
+    expires_at: Option<DateTime<Utc>>
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct PaginatedResult {
-    total_pages: usize,
+    total_items: u64,
+    total_pages: u64,
     current_page: usize,
+    page_size: usize,
+    recipes: Vec<Recipe>
+}
+
+#[derive(Serialize)]
+pub struct CursorPaginatedResult {
+    page_size: usize,
+    next_cursor: Option<String>,
+    prev_cursor: Option<String>,
     recipes: Vec<Recipe>
 }
 
+impl CursorPaginatedResult {
+    fn new(page: KeysetPage, cursor: Option<&Cursor>, page_size: usize, codec: &CursorCodec) -> Self {
+        let direction = cursor.map_or(Direction::Next, |cursor| cursor.direction);
+
+        // Whichever way we moved, there is always something back where we came from.
+        let (has_next, has_prev) = match direction {
+            Direction::Next => (page.has_more, cursor.is_some()),
+            Direction::Prev => (true, page.has_more)
+        };
+
+        let token = |recipe: Option<&Recipe>, direction: Direction| recipe
+            .and_then(|recipe| recipe._id)
+            .map(|id| codec.encode(&Cursor { id, direction }));
+
+        CursorPaginatedResult {
+            page_size,
+            next_cursor: if has_next { token(page.recipes.last(), Direction::Next) } else { None },
+            prev_cursor: if has_prev { token(page.recipes.first(), Direction::Prev) } else { None },
+            recipes: page.recipes
+        }
+    }
+}
+
+impl PaginatedResult {
+    fn new(page: Page, current_page: usize, page_size: usize) -> Self {
+        PaginatedResult {
+            total_items: page.total_items,
+            total_pages: page.total_items.div_ceil(page_size as u64),
+            current_page,
+            page_size,
+            recipes: page.recipes
+        }
+    }
+}
+
 
 pub async fn get_data(
-    database: web::Data<Database>,
-    req: HttpRequest,
-    filters: Option<web::Json<Filters>>,
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
+    codec: web::Data<CursorCodec>,
+    filters: web::Query<Filters>,
     params: web::Query<MultipleQueryParams>
-) -> impl Responder {
-    let auth_header = req.headers().get("Authorization");
-
-    if let Some(header_value) = auth_header {
-        if let Ok(header_str) = header_value.to_str() {
-
-            let api_key = header_str.trim();
-            let expected_token = "0e89498d5b964f4aa2063ab28eb23a45";
-
-            let limit = params.limit.unwrap_or(5);
-            let page = params.page.unwrap_or(1);
-
-            if !api_key.is_empty() && api_key == expected_token {
-                let collection = database.collection("Recipes");
-
-                if limit > 0 && page > 0 {
-                    return HttpResponse::BadRequest().finish();
-                }
-
-                if let Some(filters) = filters {
-                    let factored_filters = Filters {
-                        query: filters.query.clone(),
-                        diets: filters.diets.clone(),
-                        max_ready_time: filters.max_ready_time,
-                        min_servings: filters.min_servings,
-                        cuisines: filters.cuisines.clone(),
-                        dish_types: filters.dish_types.clone(),
-                        max_calories: filters.max_calories,
-                        max_fats: filters.max_carbs,
-                        max_carbs: filters.max_carbs,
-                        max_glycemic_index: filters.max_glycemic_index,
-                        healthy: filters.healthy
-                    };
-
-                    let recipes_result = filter_recipes(&collection, factored_filters, page).await;
-                    match recipes_result {
-                        Ok(recipes) => HttpResponse::Ok().json(recipes),
-                        Err(_) => HttpResponse::InternalServerError().finish()
-                    };
-                } else {
-                    if limit > 0 {
-                        let recipes_result = db::list_recipes(&collection, Some(limit), None).await;
-                        
-                        match recipes_result {
-                            Ok(recipes) => HttpResponse::Ok().json(recipes),
-                            Err(_) => HttpResponse::InternalServerError().finish()
-                        };
-                    } else {
-                        HttpResponse::BadRequest().finish();
-                    }
-                }
+) -> Result<HttpResponse, AppError> {
+    search(recipes.get_ref().as_ref(), &codec, filters.into_inner(), &params).await
+}
 
-            }
+pub async fn search_data(
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
+    codec: web::Data<CursorCodec>,
+    filters: web::Json<Filters>,
+    params: web::Query<MultipleQueryParams>
+) -> Result<HttpResponse, AppError> {
+    search(recipes.get_ref().as_ref(), &codec, filters.into_inner(), &params).await
+}
+
+async fn search(recipes: &dyn RecipeRepository, codec: &CursorCodec, filters: Filters, params: &MultipleQueryParams) -> Result<HttpResponse, AppError> {
+    validate_filters(&filters)?;
+
+    let page = params.page.unwrap_or(1);
+    let page_size = params.page_size.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+
+    if page == 0 {
+        return Err(AppError::validation("page must be at least 1"));
+    }
 
+    if page > MAX_PAGE {
+        return Err(AppError::validation(format!("page must be at most {}", MAX_PAGE)));
+    }
+
+    if page_size == 0 {
+        return Err(AppError::validation("page_size must be at least 1"));
+    }
+
+    let sort = match params.sort.as_deref() {
+        Some(sort) => parse_sort(sort)?,
+        None => Vec::new()
+    };
+
+    if params.cursor.is_some() || params.pagination.as_deref() == Some("cursor") {
+        if !sort.is_empty() {
+            return Err(AppError::validation("sort cannot be combined with cursor pagination, which always orders by insertion"));
         }
+
+        let cursor = match params.cursor.as_deref() {
+            Some(token) => Some(codec.decode(token)?),
+            None => None
+        };
+
+        let result = recipes.filter_keyset(filters, cursor.as_ref(), page_size).await?;
+        return Ok(HttpResponse::Ok().json(CursorPaginatedResult::new(result, cursor.as_ref(), page_size, codec)));
     }
 
-    HttpResponse::Unauthorized().finish()
+    let result = recipes.filter(filters, &sort, page, page_size).await?;
+    Ok(HttpResponse::Ok().json(PaginatedResult::new(result, page, page_size)))
+}
+
+pub async fn search_by_ingredients(
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
+    payload: web::Json<PantryPayload>
+) -> Result<HttpResponse, AppError> {
+    let have: Vec<String> = payload.have.iter().map(|name| ingredients::normalize(name)).filter(|name| !name.is_empty()).collect();
+    let exclude: Vec<String> = payload.exclude.iter().map(|name| ingredients::normalize(name)).filter(|name| !name.is_empty()).collect();
+    let limit = payload.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+
+    if have.is_empty() {
+        return Err(AppError::validation("have must list at least one ingredient"));
+    }
 
+    let mut terms: Vec<String> = have.iter().flat_map(|name| ingredients::search_terms(name)).collect();
+    terms.sort();
+    terms.dedup();
+
+    let candidates = recipes.find_by_ingredient_terms(&terms, MAX_PANTRY_CANDIDATES).await?;
+
+    let matches = candidates.into_iter()
+        .filter_map(|recipe| ingredients::match_recipe(recipe, &have, &exclude))
+        .collect();
+
+    let mut results = ingredients::rank(matches);
+    results.truncate(limit);
+
+    Ok(HttpResponse::Ok().json(PantryResult { results }))
+}
+
+pub async fn get_recipe(
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
+    req: HttpRequest,
+    path: web::Path<String>
+) -> Result<HttpResponse, AppError> {
+    fetch_recipe(recipes.get_ref().as_ref(), &req, &path).await
 }
 
 pub async fn get_single_data(
-    database: web::Data<Database>,
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
     req: HttpRequest,
     params: web::Query<SingleQueryParams>
-) -> impl Responder {
-    let auth_header = req.headers().get("Authorization");
-
-    if let Some(header_value) = auth_header {
-        if let Ok(header_str) = header_value.to_str() {
-
-            let api_key = header_str.trim();
-            let expected_token = "0e89498d5b964f4aa2063ab28eb23a45";
-
-            if !api_key.is_empty() && api_key == expected_token {
-                if params.id.is_some() {
-                    let collection = database.collection("Recipes");
-
-                    return match read_recipe(&collection, &params.id.unwrap().to_string()).await {
-                        Ok(recipe) => HttpResponse::Ok().json(recipe.unwrap()),
-                        Err(_) => HttpResponse::InternalServerError().finish()
-                    };
-                } else {
-                    return HttpResponse::BadRequest().finish();
-                }
-            }
-        }
+) -> Result<HttpResponse, AppError> {
+    match &params.id {
+        Some(id) => fetch_recipe(recipes.get_ref().as_ref(), &req, id).await,
+        None => Err(AppError::validation("id is required"))
     }
-
-    HttpResponse::Unauthorized().finish()
 }
 
+// Ids are generated unless the client brings its own, in which case a taken one is a conflict rather than an overwrite.
 pub async fn create_data(
-    database: web::Data<Database>,
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
     payload: web::Json<Payload>,
-    req: HttpRequest,
-) -> impl Responder {
-    let auth_header = req.headers().get("Authorization");
+    params: web::Query<CreateParams>
+) -> Result<HttpResponse, AppError> {
+    let recipe = payload.into_inner().recipe.into_user_recipe();
+
+    validate_recipe(&recipe)?;
+
+    if !params.allow_duplicate {
+        if let Some(existing) = recipes.find_duplicate(&recipe).await? {
+            return Err(AppError::Conflict(format!(
+                "Recipe looks like a duplicate of recipe {}; pass allow_duplicate=true to create it anyway",
+                existing
+            )));
+        }
+    }
 
-    if let Some(header_value) = auth_header {
-        if let Ok(header_str) = header_value.to_str() {
+    let created = recipes.create(&recipe).await?;
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/recipes/{}", created.id)))
+        .insert_header(ETag(entity_tag(&created)))
+        .json(created))
+}
 
-            let api_key = header_str.trim();
-            let expected_token = "0e89498d5b964f4aa2063ab28eb23a45";
+pub async fn bulk_data(
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
+    payload: web::Json<BulkPayload>
+) -> Result<HttpResponse, AppError> {
+    let payload = payload.into_inner();
 
-            if !api_key.is_empty() && api_key == expected_token {
-                let collection = database.collection("Recipes");
+    if payload.operations.is_empty() {
+        return Err(AppError::validation("operations must list at least one operation"));
+    }
 
-                return match create_recipe(&collection, &payload.recipe).await {
-                    Ok(_) => HttpResponse::Created().finish(),
-                    Err(_) => HttpResponse::InternalServerError().finish()
-                };
-            }
-        }
+    if payload.operations.len() > MAX_BULK_OPERATIONS {
+        return Err(AppError::validation(format!("At most {} operations are allowed per request", MAX_BULK_OPERATIONS)));
     }
 
-    HttpResponse::Unauthorized().finish()
+    let result = bulk::execute(recipes.get_ref().as_ref(), payload).await?;
+
+    if result.all_succeeded() {
+        Ok(HttpResponse::Ok().json(result))
+    } else {
+        Ok(HttpResponse::MultiStatus().json(result))
+    }
+}
+
+pub async fn delete_recipe_by_id(
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
+    req: HttpRequest,
+    path: web::Path<String>
+) -> Result<HttpResponse, AppError> {
+    remove_recipe(recipes.get_ref().as_ref(), &req, &path).await
 }
 
 pub async fn delete_data(
-    database: web::Data<Database>,
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
     req: HttpRequest,
     params: web::Query<SingleQueryParams>
-) -> impl Responder {
-    let auth_header = req.headers().get("Authorization");
-
-    if let Some(header_value) = auth_header {
-        if let Ok(header_str) = header_value.to_str() {
-
-            let api_key = header_str.trim();
-            let expected_token = "0e89498d5b964f4aa2063ab28eb23a45";
-
-            if !api_key.is_empty() && api_key == expected_token {
-                if params.id.is_some() {
-                    let collection = database.collection("Recipes");
-
-                    return match delete_recipe(&collection, &params.id.unwrap().to_string()).await {
-                        Ok(_) => HttpResponse::Accepted().finish(),
-                        Err(_) => HttpResponse::InternalServerError().finish()
-                    };
-                } else {
-                    return HttpResponse::BadRequest().finish();
-                }
-            }
-        }
+) -> Result<HttpResponse, AppError> {
+    match &params.id {
+        Some(id) => remove_recipe(recipes.get_ref().as_ref(), &req, id).await,
+        None => Err(AppError::validation("id is required"))
+    }
+}
+
+pub async fn update_recipe_by_id(
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
+    req: HttpRequest,
+    payload: web::Json<Payload>,
+    path: web::Path<String>
+) -> Result<HttpResponse, AppError> {
+    replace_recipe(recipes.get_ref().as_ref(), &req, &path, payload.into_inner().recipe).await
+}
+
+// PATCH takes a merge patch (RFC 7396) or a JSON Patch (RFC 6902), told apart by the content type.
+pub async fn patch_recipe_by_id(
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
+    req: HttpRequest,
+    body: web::Bytes,
+    path: web::Path<String>
+) -> Result<HttpResponse, AppError> {
+    let patch = RecipePatch::parse(req.content_type(), &body)?;
+
+    let Some(current) = recipes.read(&path).await? else {
+        return Err(AppError::NotFound(format!("Recipe {}", path)));
+    };
+
+    if expected_versions(&req).is_some_and(|versions| !versions.contains(&current.version)) {
+        return Err(RecipeError::VersionMismatch(current.id).into());
     }
 
-    HttpResponse::Unauthorized().finish()
+    let patched = patch.apply(&current)?;
+
+    // The patch was computed against the version just read, so only that version may receive it.
+    let patched = recipes.patch(&path, &patched, current.version).await?;
+    Ok(HttpResponse::Ok().insert_header(ETag(entity_tag(&patched))).json(patched))
 }
 
 pub async fn update_data(
-    database: web::Data<Database>,
-    payload: web::Json<Payload>,
+    recipes: web::Data<Arc<dyn RecipeRepository>>,
     req: HttpRequest,
+    payload: web::Json<Payload>,
     params: web::Query<SingleQueryParams>
-) -> impl Responder {
-    let auth_header = req.headers().get("Authorization");
-
-    if let Some(header_value) = auth_header {
-        if let Ok(header_str) = header_value.to_str() {
-
-            let api_key = header_str.trim();
-            let expected_token = "0e89498d5b964f4aa2063ab28eb23a45";
-
-            if !api_key.is_empty() && api_key == expected_token {
-                if params.id.is_some() {
-                    let collection = database.collection("Recipes");
-    
-                    return match update_recipe(&collection, &params.id.unwrap().to_string(), payload.recipe.to_document()).await {
-                        Ok(_) => HttpResponse::Created().finish(),
-                        Err(_) => HttpResponse::InternalServerError().finish()
-                    };
-                } else {
-                    return HttpResponse::BadRequest().finish();
-                }
+) -> Result<HttpResponse, AppError> {
+    match &params.id {
+        Some(id) => replace_recipe(recipes.get_ref().as_ref(), &req, id, payload.into_inner().recipe).await,
+        None => Err(AppError::validation("id is required"))
+    }
+}
+
+async fn fetch_recipe(recipes: &dyn RecipeRepository, req: &HttpRequest, id: &str) -> Result<HttpResponse, AppError> {
+    let Some(recipe) = recipes.read(id).await? else {
+        return Err(AppError::NotFound(format!("Recipe {}", id)));
+    };
+
+    if not_modified(req, &recipe) {
+        return Ok(HttpResponse::NotModified().insert_header(ETag(entity_tag(&recipe))).finish());
+    }
+
+    Ok(HttpResponse::Ok().insert_header(ETag(entity_tag(&recipe))).json(recipe))
+}
+
+async fn remove_recipe(recipes: &dyn RecipeRepository, req: &HttpRequest, id: &str) -> Result<HttpResponse, AppError> {
+    recipes.delete(id, expected_versions(req).as_deref()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn replace_recipe(recipes: &dyn RecipeRepository, req: &HttpRequest, id: &str, recipe: Recipe) -> Result<HttpResponse, AppError> {
+    let recipe = validate_replacement(id, recipe)?;
+
+    let updated = recipes.update(id, &recipe, expected_versions(req).as_deref()).await?;
+    Ok(HttpResponse::Ok().insert_header(ETag(entity_tag(&updated))).json(updated))
+}
+
+pub async fn create_key(
+    store: web::Data<Arc<dyn ApiKeyStore>>,
+    payload: web::Json<KeyPayload>
+) -> Result<HttpResponse, AppError> {
+    let (raw_key, key) = store.create(&payload.name, payload.scopes.clone(), payload.expires_at).await?;
+
+    Ok(HttpResponse::Created().json(CreatedKey {
+        id: key.id,
+        name: key.name,
+        key: raw_key,
+        scopes: key.scopes,
+        expires_at: key.expires_at
+    }))
+}
+
+pub async fn revoke_key(
+    store: web::Data<Arc<dyn ApiKeyStore>>,
+    path: web::Path<String>
+) -> Result<HttpResponse, AppError> {
+    if store.revoke(&path).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AppError::NotFound(format!("API key {}", path)))
+    }
+}
+
+fn deprecated(successor: &str) -> DefaultHeaders {
+    DefaultHeaders::new()
+        .add(("Deprecation", "true"))
+        .add((LINK, format!("<{}>; rel=\"successor-version\"", successor)))
+}
+
+// Every route and its required scope. The app supplies the repository, key store and cursor codec, and wraps
+// this in `ApiKeyAuth`, which the scope checks rely on.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::JsonConfig::default().error_handler(errors::json_error_handler))
+        .app_data(web::QueryConfig::default().error_handler(errors::query_error_handler))
+        .app_data(web::PathConfig::default().error_handler(errors::path_error_handler))
+        .service(web::resource("/recipes")
+            .route(web::get().to(get_data).wrap(RequireScope::new(Scope::RecipesRead)))
+            .route(web::post().to(create_data).wrap(RequireScope::new(Scope::RecipesWrite)))
+            .default_service(errors::method_not_allowed(&["GET", "POST"]))
+        )
+        .service(web::resource("/recipes/search")
+            .route(web::post().to(search_data).wrap(RequireScope::new(Scope::RecipesRead)))
+            .default_service(errors::method_not_allowed(&["POST"]))
+        )
+        // Imports can run to hundreds of recipes, well past the default 32 KiB body limit.
+        .service(web::resource("/recipes/bulk")
+            .app_data(web::JsonConfig::default().limit(16 * 1024 * 1024).error_handler(errors::json_error_handler))
+            .route(web::post().to(bulk_data).wrap(RequireScope::new(Scope::RecipesWrite)))
+            .default_service(errors::method_not_allowed(&["POST"]))
+        )
+        .service(web::resource("/recipes/by-ingredients")
+            .route(web::post().to(search_by_ingredients).wrap(RequireScope::new(Scope::RecipesRead)))
+            .default_service(errors::method_not_allowed(&["POST"]))
+        )
+        .service(web::resource("/recipes/{id}")
+            .route(web::get().to(get_recipe).wrap(RequireScope::new(Scope::RecipesRead)))
+            .route(web::put().to(update_recipe_by_id).wrap(RequireScope::new(Scope::RecipesWrite)))
+            .route(web::patch().to(patch_recipe_by_id).wrap(RequireScope::new(Scope::RecipesWrite)))
+            .route(web::delete().to(delete_recipe_by_id).wrap(RequireScope::new(Scope::RecipesWrite)))
+            .default_service(errors::method_not_allowed(&["GET", "PUT", "PATCH", "DELETE"]))
+        )
+        .service(web::resource("/keys")
+            .route(web::post().to(create_key).wrap(RequireScope::new(Scope::Admin)))
+            .default_service(errors::method_not_allowed(&["POST"]))
+        )
+        .service(web::resource("/keys/{id}")
+            .route(web::delete().to(revoke_key).wrap(RequireScope::new(Scope::Admin)))
+            .default_service(errors::method_not_allowed(&["DELETE"]))
+        )
+        // Deprecated aliases, kept until clients have moved to the routes above.
+        .service(web::resource("/recipe").wrap(deprecated("/recipes/{id}")).wrap(RequireScope::new(Scope::RecipesRead)).to(get_single_data))
+        .service(web::resource("/create").wrap(deprecated("/recipes")).wrap(RequireScope::new(Scope::RecipesWrite)).to(create_data))
+        .service(web::resource("/update").wrap(deprecated("/recipes/{id}")).wrap(RequireScope::new(Scope::RecipesWrite)).to(update_data))
+        .service(web::resource("/delete").wrap(deprecated("/recipes/{id}")).wrap(RequireScope::new(Scope::RecipesWrite)).to(delete_data))
+        .default_service(web::to(errors::not_found));
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::MessageBody, dev::{Service, ServiceResponse}, http::{header, StatusCode}, test, App};
+    use actix_http::Request;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{auth::{self, ApiKeyAuth, InMemoryApiKeyStore}, test_support::{fixture_json, repository}};
+
+    const ADMIN_KEY: &str = "test-admin-key";
+
+    // The production routes behind `ApiKeyAuth`, with the fixture loaded and one admin key.
+    async fn app() -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error> {
+        let keys: Arc<dyn ApiKeyStore> = Arc::new(InMemoryApiKeyStore::default());
+        auth::bootstrap(keys.as_ref(), ADMIN_KEY).await.unwrap();
+
+        test::init_service(App::new()
+            .wrap(ApiKeyAuth::new(keys.clone()))
+            .app_data(web::Data::new(Arc::new(repository()) as Arc<dyn RecipeRepository>))
+            .app_data(web::Data::new(keys))
+            .app_data(web::Data::new(CursorCodec::new(b"test".to_vec())))
+            .configure(configure)
+        ).await
+    }
+
+    fn as_admin(request: test::TestRequest) -> test::TestRequest {
+        request.insert_header((header::AUTHORIZATION, format!("Bearer {}", ADMIN_KEY)))
+    }
+
+    // A fixture recipe as a client would send it in a replacement, without the id the path carries.
+    fn recipe_json(id: &str) -> Value {
+        let mut recipe = fixture_json(id);
+        recipe.as_object_mut().unwrap().remove("id");
+        recipe
+    }
+
+    async fn error_code(response: ServiceResponse<impl MessageBody>) -> String {
+        let body: Value = test::read_body_json(response).await;
+        body["code"].as_str().unwrap().to_string()
+    }
+
+    #[actix_web::test]
+    async fn missing_recipe_is_not_found_on_get_put_and_delete() {
+        let app = app().await;
+
+        let requests = [
+            as_admin(test::TestRequest::get()).uri("/recipes/missing"),
+            as_admin(test::TestRequest::put()).uri("/recipes/missing").set_json(json!({ "recipe": recipe_json("716429") })),
+            as_admin(test::TestRequest::delete()).uri("/recipes/missing")
+        ];
+
+        for request in requests {
+            let response = test::call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            assert_eq!(error_code(response).await, "not_found");
+        }
+    }
+
+    #[actix_web::test]
+    async fn if_match_tells_a_stale_version_from_a_missing_recipe() {
+        let app = app().await;
+
+        for id in ["716429", "missing"] {
+            let expected = if id == "missing" { StatusCode::NOT_FOUND } else { StatusCode::PRECONDITION_FAILED };
 
+            let requests = [
+                as_admin(test::TestRequest::put()).uri(&format!("/recipes/{}", id)).set_json(json!({ "recipe": recipe_json("716429") })),
+                as_admin(test::TestRequest::delete()).uri(&format!("/recipes/{}", id))
+            ];
+
+            for request in requests {
+                let response = test::call_service(&app, request.insert_header((header::IF_MATCH, "\"7\"")).to_request()).await;
+                assert_eq!(response.status(), expected, "{}", id);
             }
         }
+
+        let request = as_admin(test::TestRequest::delete()).uri("/recipes/716429").insert_header((header::IF_MATCH, "\"1\""));
+        assert_eq!(test::call_service(&app, request.to_request()).await.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[actix_web::test]
+    async fn replaces_and_deletes_a_recipe() {
+        let app = app().await;
+
+        let mut recipe = recipe_json("782601");
+        recipe["servings"] = json!(8);
+
+        let request = as_admin(test::TestRequest::put()).uri("/recipes/782601").set_json(json!({ "recipe": recipe }));
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), "\"2\"");
+
+        let replaced: Value = test::read_body_json(response).await;
+        assert_eq!((replaced["id"].as_str(), replaced["servings"].as_i64(), replaced["version"].as_i64()), (Some("782601"), Some(8), Some(2)));
+
+        let response = test::call_service(&app, as_admin(test::TestRequest::delete()).uri("/recipes/782601").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(test::call_service(&app, as_admin(test::TestRequest::get()).uri("/recipes/782601").to_request()).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn page_past_the_limit_is_rejected() {
+        let app = app().await;
+
+        let request = as_admin(test::TestRequest::get()).uri("/recipes?page=1000000000000000000&page_size=100");
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = as_admin(test::TestRequest::get()).uri(&format!("/recipes?page={}&page_size=100", MAX_PAGE));
+        assert_eq!(test::call_service(&app, request.to_request()).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn lists_and_searches_with_page_and_cursor_pagination() {
+        let app = app().await;
+
+        let request = as_admin(test::TestRequest::get()).uri("/recipes?diets=vegan&sort=calories&page_size=1");
+        let body: Value = test::read_body_json(test::call_service(&app, request.to_request()).await).await;
+        assert_eq!(body["total_items"], 2);
+        assert_eq!(body["total_pages"], 2);
+        assert_eq!(body["recipes"][0]["id"], "715594");
+
+        let request = as_admin(test::TestRequest::post()).uri("/recipes/search?pagination=cursor&page_size=3").set_json(json!({}));
+        let body: Value = test::read_body_json(test::call_service(&app, request.to_request()).await).await;
+        assert_eq!(body["recipes"].as_array().unwrap().len(), 3);
+        assert!(body["prev_cursor"].is_null());
+
+        let cursor = body["next_cursor"].as_str().unwrap();
+        let request = as_admin(test::TestRequest::post()).uri(&format!("/recipes/search?cursor={}&page_size=3", cursor)).set_json(json!({}));
+        let body: Value = test::read_body_json(test::call_service(&app, request.to_request()).await).await;
+        assert_eq!(body["recipes"].as_array().unwrap().len(), 1);
+        assert!(body["next_cursor"].is_null());
+        assert!(body["prev_cursor"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn creates_and_patches_a_recipe() {
+        let app = app().await;
+
+        let mut recipe = recipe_json("716429");
+        recipe["title"] = json!("Weeknight Cauliflower Pasta");
+
+        let request = as_admin(test::TestRequest::post()).uri("/recipes").set_json(json!({ "recipe": recipe }));
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+        let created: Value = test::read_body_json(response).await;
+        assert_eq!(created["source"], "user");
+        assert_eq!(location, format!("/recipes/{}", created["id"].as_str().unwrap()));
+
+        let request = as_admin(test::TestRequest::patch()).uri(&location)
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(r#"{"servings": 4}"#);
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let patched: Value = test::read_body_json(response).await;
+        assert_eq!(patched["servings"], 4);
+        assert_eq!(patched["version"], 2);
     }
 
-    HttpResponse::Unauthorized().finish()
-}
\ No newline at end of file
+    #[actix_web::test]
+    async fn invalid_recipe_is_unprocessable_with_each_field() {
+        let app = app().await;
+
+        let mut recipe = recipe_json("716429");
+        recipe["servings"] = json!(0);
+        recipe["cuisines"] = json!(["italian", "martian"]);
+
+        let request = as_admin(test::TestRequest::post()).uri("/recipes").set_json(json!({ "recipe": recipe }));
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "unprocessable_entity");
+        assert_eq!(body["details"], json!([
+            { "field": "servings", "message": "must be between 1 and 100" },
+            { "field": "cuisines[1]", "message": "'martian' is not a recognised value" }
+        ]));
+    }
+
+    #[actix_web::test]
+    async fn bulk_reports_each_operation() {
+        let app = app().await;
+
+        let mut recipe = recipe_json("715594");
+        recipe["title"] = json!("Oven Fries");
+
+        let request = as_admin(test::TestRequest::post()).uri("/recipes/bulk").set_json(json!({
+            "ordered": false,
+            "operations": [
+                { "op": "create", "recipe": recipe },
+                { "op": "update", "id": "782601", "recipe": recipe_json("782601") },
+                { "op": "delete", "id": "missing" }
+            ]
+        }));
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+
+        let body: Value = test::read_body_json(response).await;
+        let statuses: Vec<&Value> = body["results"].as_array().unwrap().iter().map(|result| &result["status"]).collect();
+        assert_eq!(statuses, [201, 200, 404]);
+    }
+
+    #[actix_web::test]
+    async fn pantry_search_ranks_and_excludes() {
+        let app = app().await;
+        let have = ["potatoes", "olive oil", "garlic", "basil", "salt"];
+
+        let request = as_admin(test::TestRequest::post()).uri("/recipes/by-ingredients").set_json(json!({ "have": have }));
+        let body: Value = test::read_body_json(test::call_service(&app, request.to_request()).await).await;
+        assert_eq!(body["results"][0]["recipe"]["id"], "715594");
+        assert_eq!(body["results"][0]["missing_ingredients"], json!([]));
+
+        let request = as_admin(test::TestRequest::post()).uri("/recipes/by-ingredients").set_json(json!({ "have": have, "exclude": ["basil"] }));
+        let body: Value = test::read_body_json(test::call_service(&app, request.to_request()).await).await;
+        assert!(body["results"].as_array().unwrap().iter().all(|result| result["recipe"]["id"] != "715594"));
+    }
+
+    #[actix_web::test]
+    async fn creates_and_revokes_a_key() {
+        let app = app().await;
+
+        let request = as_admin(test::TestRequest::post()).uri("/keys").set_json(json!({ "name": "ci" }));
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let key: Value = test::read_body_json(response).await;
+        assert_eq!(key["scopes"], json!(["recipes:read"]));
+
+        let uri = format!("/keys/{}", key["id"].as_str().unwrap());
+        assert_eq!(test::call_service(&app, as_admin(test::TestRequest::delete()).uri(&uri).to_request()).await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(test::call_service(&app, as_admin(test::TestRequest::delete()).uri("/keys/missing").to_request()).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn routes_answer_unknown_methods_paths_and_deprecated_aliases() {
+        let app = app().await;
+
+        let response = test::call_service(&app, as_admin(test::TestRequest::post()).uri("/recipes/716429").to_request()).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers().get(header::ALLOW).unwrap(), "GET, PUT, PATCH, DELETE");
+        assert_eq!(error_code(response).await, "method_not_allowed");
+
+        let response = test::call_service(&app, as_admin(test::TestRequest::get()).uri("/nope").to_request()).await;
+        assert_eq!(error_code(response).await, "not_found");
+
+        let response = test::call_service(&app, as_admin(test::TestRequest::get()).uri("/recipe?id=716429").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Deprecation").unwrap(), "true");
+    }
+}
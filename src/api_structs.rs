@@ -1,6 +1,6 @@
 use serde::{ Serialize, Deserialize };
 
-use crate::models::{self, Recipe};
+use crate::{allergens, models::{self, Recipe, RecipeSource}};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -27,47 +27,53 @@ impl APIRecipe {
     pub fn to_recipe(&self) -> Recipe {
         let ingredients: Vec<models::Ingredient> = self.extended_ingredients.iter().map(|api_ingredient| models::Ingredient {
             name: api_ingredient.name.clone(),
-            amount: api_ingredient.amount.clone(),
+            amount: api_ingredient.amount,
             unit: api_ingredient.unit.clone()
         }).collect();
 
         let nutrition: models::Nutrition = models::Nutrition {
             nutrients: self.nutrition.nutrients.iter().map(|api_nutrient| models::Nutrient {
                 name: api_nutrient.name.clone(),
-                amount: api_nutrient.amount.clone(),
+                amount: api_nutrient.amount,
                 unit: api_nutrient.unit.clone()
             }).collect(),
             properties: self.nutrition.properties.iter().map(|api_property| models::Property {
                 name: api_property.name.clone(),
-                amount: api_property.amount.clone(),
+                amount: api_property.amount,
             }).collect()
         };
 
         
         let instructions: Vec<models::Step> = self.analyzed_instructions[0].steps.iter().map(|api_instruction| models::Step {
-            number: api_instruction.number.clone(),
+            number: api_instruction.number,
             step: api_instruction.step.clone(),
         }).collect();
         
-        Recipe {
+        let mut recipe = Recipe {
             _id: None,
-            id: self.id.clone().to_string(),
+            id: self.id.to_string(),
             title: self.title.clone(),
             summary: self.summary.clone(),
             image: self.image.clone(),
-            vegetarian: self.vegetarian.clone(),
-            vegan: self.vegan.clone(),
-            gluten_free: self.gluten_free.clone(),
-            dairy_free: self.dairy_free.clone(),
-            ready_in_minutes: self.ready_in_minutes.clone(),
-            servings: self.servings.clone(),
+            vegetarian: self.vegetarian,
+            vegan: self.vegan,
+            gluten_free: self.gluten_free,
+            dairy_free: self.dairy_free,
+            ready_in_minutes: self.ready_in_minutes,
+            servings: self.servings,
             ingredients,
             nutrition,
             cuisines: self.cuisines.clone(),
             dish_types: self.dish_types.clone(),
             diets: self.diets.clone(),
-            instructions
-        }
+            instructions,
+            allergens: Vec::new(),
+            version: 0,
+            source: RecipeSource::Spoonacular
+        };
+
+        recipe.allergens = allergens::detect(&recipe);
+        recipe
     }
 }
 
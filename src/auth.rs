@@ -0,0 +1,455 @@
+use std::{collections::HashMap, future::{ready, Ready}, rc::Rc, sync::{Arc, RwLock}};
+use actix_web::{
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+    HttpMessage,
+    ResponseError
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::LocalBoxFuture;
+use mongodb::{bson::{doc, oid::ObjectId}, options::IndexOptions, Collection, IndexModel};
+use rand::RngCore;
+use serde::{ Serialize, Deserialize };
+use sha2::{Digest, Sha256};
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use thiserror::Error;
+
+use crate::errors::AppError;
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("Database error")]
+    DatabaseError(#[from] mongodb::error::Error),
+    #[error("SQLite error")]
+    SqliteError(#[from] sqlx::Error)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    #[serde(rename = "recipes:read")]
+    RecipesRead,
+    #[serde(rename = "recipes:write")]
+    RecipesWrite,
+    #[serde(rename = "admin")]
+    Admin
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+    pub id: String,
+    pub name: String,
+    pub key_hash: String,
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked: bool
+}
+
+impl ApiKey {
+    pub fn is_active(&self) -> bool {
+        !self.revoked && self.expires_at.is_none_or(|expires_at| expires_at > Utc::now())
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|granted| *granted == scope || *granted == Scope::Admin)
+    }
+}
+
+pub fn hash_key(raw_key: &str) -> String {
+    hex::encode(Sha256::digest(raw_key.as_bytes()))
+}
+
+fn generate_token(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    async fn insert(&self, key: ApiKey) -> Result<(), AuthError>;
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, AuthError>;
+    async fn revoke(&self, id: &str) -> Result<bool, AuthError>;
+
+    // The plaintext key is only ever returned here; the store keeps its hash.
+    async fn create(&self, name: &str, scopes: Vec<Scope>, expires_at: Option<DateTime<Utc>>) -> Result<(String, ApiKey), AuthError> {
+        let raw_key = generate_token(32);
+        let key = ApiKey {
+            _id: None,
+            id: generate_token(8),
+            name: name.to_string(),
+            key_hash: hash_key(&raw_key),
+            scopes,
+            created_at: Utc::now(),
+            expires_at,
+            revoked: false
+        };
+
+        self.insert(key.clone()).await?;
+        Ok((raw_key, key))
+    }
+
+    async fn authenticate(&self, raw_key: &str) -> Result<Option<ApiKey>, AuthError> {
+        if raw_key.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(self.find_by_hash(&hash_key(raw_key)).await?.filter(|key| key.is_active()))
+    }
+}
+
+pub struct MongoApiKeyStore {
+    collection: Collection<ApiKey>
+}
+
+impl MongoApiKeyStore {
+    pub fn new(collection: Collection<ApiKey>) -> Self {
+        MongoApiKeyStore { collection }
+    }
+
+    // Every request looks its key up by hash, and two keys must never share one. A no-op once the index exists.
+    pub async fn ensure_indexes(&self) -> Result<(), AuthError> {
+        let index = IndexModel::builder()
+            .keys(doc! { "key_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.collection.create_index(index, None).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for MongoApiKeyStore {
+    async fn insert(&self, key: ApiKey) -> Result<(), AuthError> {
+        self.collection.insert_one(key, None).await?;
+        Ok(())
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, AuthError> {
+        Ok(self.collection.find_one(doc! { "key_hash": key_hash }, None).await?)
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, AuthError> {
+        let result = self.collection.update_one(
+            doc! { "id": id },
+            doc! { "$set": { "revoked": true } },
+            None
+        ).await?;
+        Ok(result.matched_count > 0)
+    }
+}
+
+// Shares the recipes' database, so a SQLite deployment needs nothing else.
+pub struct SqliteApiKeyStore {
+    pool: SqlitePool
+}
+
+impl SqliteApiKeyStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        SqliteApiKeyStore { pool }
+    }
+}
+
+fn decode_error(e: impl std::error::Error + Send + Sync + 'static) -> sqlx::Error {
+    sqlx::Error::Decode(Box::new(e))
+}
+
+fn api_key_from_row(row: &SqliteRow) -> Result<ApiKey, sqlx::Error> {
+    let timestamp = |value: String| DateTime::parse_from_rfc3339(&value).map(|t| t.with_timezone(&Utc)).map_err(decode_error);
+
+    Ok(ApiKey {
+        _id: None,
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        key_hash: row.try_get("key_hash")?,
+        scopes: serde_json::from_str(row.try_get("scopes")?).map_err(decode_error)?,
+        created_at: timestamp(row.try_get("created_at")?)?,
+        expires_at: row.try_get::<Option<String>, _>("expires_at")?.map(timestamp).transpose()?,
+        revoked: row.try_get("revoked")?
+    })
+}
+
+#[async_trait]
+impl ApiKeyStore for SqliteApiKeyStore {
+    async fn insert(&self, key: ApiKey) -> Result<(), AuthError> {
+        sqlx::query("INSERT INTO api_keys (id, name, key_hash, scopes, created_at, expires_at, revoked) VALUES (?, ?, ?, ?, ?, ?, ?)")
+            .bind(&key.id)
+            .bind(&key.name)
+            .bind(&key.key_hash)
+            .bind(serde_json::to_string(&key.scopes).map_err(decode_error)?)
+            .bind(key.created_at.to_rfc3339())
+            .bind(key.expires_at.map(|expires_at| expires_at.to_rfc3339()))
+            .bind(key.revoked)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, AuthError> {
+        let row = sqlx::query("SELECT * FROM api_keys WHERE key_hash = ?").bind(key_hash).fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(api_key_from_row).transpose()?)
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, AuthError> {
+        let result = sqlx::query("UPDATE api_keys SET revoked = TRUE WHERE id = ?").bind(id).execute(&self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryApiKeyStore {
+    keys: RwLock<HashMap<String, ApiKey>>
+}
+
+#[async_trait]
+impl ApiKeyStore for InMemoryApiKeyStore {
+    async fn insert(&self, key: ApiKey) -> Result<(), AuthError> {
+        self.keys.write().unwrap().insert(key.key_hash.clone(), key);
+        Ok(())
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, AuthError> {
+        Ok(self.keys.read().unwrap().get(key_hash).cloned())
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, AuthError> {
+        let mut keys = self.keys.write().unwrap();
+        match keys.values_mut().find(|key| key.id == id) {
+            Some(key) => {
+                key.revoked = true;
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+}
+
+// Seeds the store with a pre-shared admin key so existing clients keep working after a fresh deploy.
+pub async fn bootstrap(store: &dyn ApiKeyStore, raw_key: &str) -> Result<(), AuthError> {
+    let key_hash = hash_key(raw_key);
+
+    if store.find_by_hash(&key_hash).await?.is_none() {
+        store.insert(ApiKey {
+            _id: None,
+            id: generate_token(8),
+            name: "bootstrap".to_string(),
+            key_hash,
+            scopes: vec![Scope::Admin],
+            created_at: Utc::now(),
+            expires_at: None,
+            revoked: false
+        }).await?;
+    }
+
+    Ok(())
+}
+
+pub struct ApiKeyAuth {
+    store: Arc<dyn ApiKeyStore>
+}
+
+impl ApiKeyAuth {
+    pub fn new(store: Arc<dyn ApiKeyStore>) -> Self {
+        ApiKeyAuth { store }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiKeyAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ApiKeyAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiKeyAuthMiddleware {
+            service: Rc::new(service),
+            store: self.store.clone()
+        }))
+    }
+}
+
+pub struct ApiKeyAuthMiddleware<S> {
+    service: Rc<S>,
+    store: Arc<dyn ApiKeyStore>
+}
+
+impl<S, B> Service<ServiceRequest> for ApiKeyAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.store.clone();
+
+        Box::pin(async move {
+            let raw_key = req.headers()
+                .get("Authorization")
+                .and_then(|header_value| header_value.to_str().ok())
+                .map(|header_str| header_str.trim())
+                .map(|header_str| header_str.strip_prefix("Bearer ").unwrap_or(header_str).trim().to_string())
+                .unwrap_or_default();
+
+            let error = match store.authenticate(&raw_key).await {
+                Ok(Some(key)) => {
+                    req.extensions_mut().insert(key);
+                    return Ok(service.call(req).await?.map_into_left_body());
+                },
+                Ok(None) => AppError::Unauthorized,
+                Err(e) => AppError::from(e)
+            };
+
+            Ok(req.into_response(error.error_response()).map_into_right_body())
+        })
+    }
+}
+
+pub struct RequireScope {
+    scope: Scope
+}
+
+impl RequireScope {
+    pub fn new(scope: Scope) -> Self {
+        RequireScope { scope }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireScopeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireScopeMiddleware {
+            service: Rc::new(service),
+            scope: self.scope
+        }))
+    }
+}
+
+pub struct RequireScopeMiddleware<S> {
+    service: Rc<S>,
+    scope: Scope
+}
+
+impl<S, B> Service<ServiceRequest> for RequireScopeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let scope = self.scope;
+
+        Box::pin(async move {
+            // ApiKeyAuth has already rejected unknown keys, so a missing key here means the route is misconfigured.
+            let allowed = req.extensions().get::<ApiKey>().map(|key| key.has_scope(scope));
+
+            let error = match allowed {
+                Some(true) => return Ok(service.call(req).await?.map_into_left_body()),
+                Some(false) => AppError::Forbidden,
+                None => AppError::Unauthorized
+            };
+
+            Ok(req.into_response(error.error_response()).map_into_right_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::{header, StatusCode}, test, web, App, HttpResponse};
+    use chrono::Duration;
+
+    use super::*;
+
+    // A store holding one key per case, with the raw keys to present.
+    async fn keys() -> (Arc<dyn ApiKeyStore>, [String; 4]) {
+        let store: Arc<dyn ApiKeyStore> = Arc::new(InMemoryApiKeyStore::default());
+        let (reader, _) = store.create("reader", vec![Scope::RecipesRead], None).await.unwrap();
+        let (writer, _) = store.create("writer", vec![Scope::RecipesWrite], None).await.unwrap();
+        let (expired, _) = store.create("expired", vec![Scope::Admin], Some(Utc::now() - Duration::minutes(1))).await.unwrap();
+        let (revoked, key) = store.create("revoked", vec![Scope::Admin], None).await.unwrap();
+        assert!(store.revoke(&key.id).await.unwrap());
+        (store, [reader, writer, expired, revoked])
+    }
+
+    #[actix_web::test]
+    async fn rejects_missing_expired_and_revoked_keys_and_missing_scopes() {
+        let (store, [reader, writer, expired, revoked]) = keys().await;
+        let app = test::init_service(App::new()
+            .wrap(ApiKeyAuth::new(store))
+            .route("/recipes", web::get().to(HttpResponse::Ok).wrap(RequireScope::new(Scope::RecipesRead)))
+        ).await;
+
+        let cases = [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some("not-a-key".to_string()), StatusCode::UNAUTHORIZED),
+            (Some(expired), StatusCode::UNAUTHORIZED),
+            (Some(revoked), StatusCode::UNAUTHORIZED),
+            (Some(writer), StatusCode::FORBIDDEN),
+            (Some(format!("Bearer {}", reader)), StatusCode::OK),
+            (Some(reader), StatusCode::OK)
+        ];
+
+        for (key, status) in cases {
+            let mut request = test::TestRequest::get().uri("/recipes");
+            if let Some(key) = &key {
+                request = request.insert_header((header::AUTHORIZATION, key.as_str()));
+            }
+            let response = test::call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), status, "{:?}", key);
+        }
+    }
+
+    #[actix_web::test]
+    async fn admin_has_every_scope() {
+        let store: Arc<dyn ApiKeyStore> = Arc::new(InMemoryApiKeyStore::default());
+        bootstrap(store.as_ref(), "admin-key").await.unwrap();
+        let app = test::init_service(App::new()
+            .wrap(ApiKeyAuth::new(store))
+            .route("/recipes", web::post().to(HttpResponse::Ok).wrap(RequireScope::new(Scope::RecipesWrite)))
+        ).await;
+
+        let request = test::TestRequest::post().uri("/recipes").insert_header((header::AUTHORIZATION, "admin-key")).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn scope_without_authentication_is_unauthorized() {
+        let app = test::init_service(App::new()
+            .route("/recipes", web::get().to(HttpResponse::Ok).wrap(RequireScope::new(Scope::RecipesRead)))
+        ).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/recipes").to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+use actix_web::{http::StatusCode, ResponseError};
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::{AppError, ErrorBody}, models::Recipe, repository::{InsertOutcome, RecipeRepository}, validation::{validate_recipe, validate_replacement}};
+
+pub const MAX_BULK_OPERATIONS: usize = 500;
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BulkOperation {
+    Create {
+        recipe: Recipe
+    },
+    Update {
+        id: String,
+        recipe: Recipe,
+        #[serde(default)]
+        version: Option<i64>
+    },
+    Delete {
+        id: String,
+        #[serde(default)]
+        version: Option<i64>
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BulkPayload {
+    #[serde(default = "default_ordered")]
+    pub ordered: bool,
+    #[serde(default)]
+    pub allow_duplicate: bool,
+    pub operations: Vec<BulkOperation>
+}
+
+// Like MongoDB itself, operations run in order and stop at the first failure unless told otherwise.
+fn default_ordered() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+pub struct BulkItemResult {
+    index: usize,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorBody>
+}
+
+#[derive(Serialize)]
+pub struct BulkResult {
+    ordered: bool,
+    succeeded: usize,
+    failed: usize,
+    results: Vec<BulkItemResult>
+}
+
+impl BulkResult {
+    pub fn all_succeeded(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+enum Prepared {
+    Create(Recipe),
+    Update { id: String, recipe: Recipe, versions: Option<Vec<i64>> },
+    Delete { id: String, versions: Option<Vec<i64>> }
+}
+
+type Outcome = Result<(StatusCode, String), AppError>;
+
+// Creates are checked up front (validation, probable duplicates) and then written with one `insert_many` per batch.
+// Taken ids are left to the store, which reports them as each batch runs, so an ordered `[delete A, create A]` works.
+// Probable duplicates are still looked up before anything runs, so deleting one doesn't free its fingerprint here.
+// The 2.x driver has no `bulk_write`, so updates and deletes go one at a time, which also keeps their statuses exact:
+// a server-side multi-update only reports how many documents matched in total.
+pub async fn execute(recipes: &dyn RecipeRepository, payload: BulkPayload) -> Result<BulkResult, AppError> {
+    let ordered = payload.ordered;
+    let mut outcomes: Vec<Option<Outcome>> = payload.operations.iter().map(|_| None).collect();
+    let mut plan: Vec<(usize, Prepared)> = Vec::new();
+
+    let creates: Vec<Recipe> = payload.operations.iter()
+        .filter_map(|operation| match operation {
+            BulkOperation::Create { recipe } => Some(recipe.clone().into_user_recipe()),
+            _ => None
+        })
+        .collect();
+
+    let fingerprints: Vec<String> = creates.iter().map(Recipe::fingerprint).collect();
+
+    let duplicates = if payload.allow_duplicate { Default::default() } else { recipes.find_duplicates(&fingerprints).await? };
+
+    let mut creates = creates.into_iter();
+    let mut seen_fingerprints = HashSet::new();
+
+    for (index, operation) in payload.operations.into_iter().enumerate() {
+        let prepared = match operation {
+            BulkOperation::Create { .. } => {
+                let recipe = creates.next().expect("one prepared recipe per create");
+                let fingerprint = recipe.fingerprint();
+
+                validate_recipe(&recipe).and_then(|_| {
+                    if let Some(existing) = duplicates.get(&fingerprint) {
+                        Err(AppError::Conflict(format!("Recipe looks like a duplicate of recipe {}", existing)))
+                    } else if !payload.allow_duplicate && !seen_fingerprints.insert(fingerprint) {
+                        Err(AppError::Conflict("Recipe looks like a duplicate of another recipe in this request".to_string()))
+                    } else {
+                        Ok(Prepared::Create(recipe))
+                    }
+                })
+            },
+            BulkOperation::Update { id, recipe, version } => validate_replacement(&id, recipe)
+                .map(|recipe| Prepared::Update { id, recipe, versions: version.map(|version| vec![version]) }),
+            BulkOperation::Delete { id, version } => Ok(Prepared::Delete { id, versions: version.map(|version| vec![version]) })
+        };
+
+        match prepared {
+            Ok(prepared) => plan.push((index, prepared)),
+            Err(e) => outcomes[index] = Some(Err(e))
+        }
+    }
+
+    // In ordered mode nothing after the first invalid operation runs.
+    let first_invalid = outcomes.iter().position(Option::is_some);
+    if ordered {
+        if let Some(first_invalid) = first_invalid {
+            plan.retain(|(index, _)| *index < first_invalid);
+        }
+    }
+
+    let mut stopped = false;
+
+    for batch in batches(plan, ordered) {
+        if stopped {
+            for (index, _) in batch {
+                outcomes[index] = Some(Err(AppError::FailedDependency));
+            }
+            continue;
+        }
+
+        for (index, outcome) in run(recipes, batch, ordered).await {
+            stopped |= ordered && outcome.is_err();
+            outcomes[index] = Some(outcome);
+        }
+    }
+
+    let results: Vec<BulkItemResult> = outcomes.into_iter().enumerate()
+        .map(|(index, outcome)| match outcome.unwrap_or(Err(AppError::FailedDependency)) {
+            Ok((status, id)) => BulkItemResult { index, status: status.as_u16(), id: Some(id), error: None },
+            Err(e) => BulkItemResult { index, status: e.status_code().as_u16(), id: None, error: Some(e.body(None)) }
+        })
+        .collect();
+
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+
+    Ok(BulkResult { ordered, succeeded: results.len() - failed, failed, results })
+}
+
+// Ordered requests batch runs of consecutive creates; unordered ones put every create in a single batch.
+fn batches(plan: Vec<(usize, Prepared)>, ordered: bool) -> Vec<Vec<(usize, Prepared)>> {
+    let mut batches: Vec<Vec<(usize, Prepared)>> = Vec::new();
+    let mut creates: Vec<(usize, Prepared)> = Vec::new();
+
+    for (index, prepared) in plan {
+        if let Prepared::Create(_) = prepared {
+            creates.push((index, prepared));
+            continue;
+        }
+
+        if ordered && !creates.is_empty() {
+            batches.push(std::mem::take(&mut creates));
+        }
+        batches.push(vec![(index, prepared)]);
+    }
+
+    if !creates.is_empty() {
+        batches.push(creates);
+    }
+
+    batches
+}
+
+async fn run(recipes: &dyn RecipeRepository, batch: Vec<(usize, Prepared)>, ordered: bool) -> Vec<(usize, Outcome)> {
+    let mut indexes = Vec::new();
+    let mut creates = Vec::new();
+    let mut outcomes = Vec::new();
+
+    for (index, prepared) in batch {
+        match prepared {
+            Prepared::Create(recipe) => {
+                indexes.push(index);
+                creates.push(recipe);
+            },
+            Prepared::Update { id, recipe, versions } => {
+                let outcome = recipes.update(&id, &recipe, versions.as_deref()).await
+                    .map(|_| (StatusCode::OK, id))
+                    .map_err(AppError::from);
+                outcomes.push((index, outcome));
+            },
+            Prepared::Delete { id, versions } => {
+                let outcome = recipes.delete(&id, versions.as_deref()).await
+                    .map(|_| (StatusCode::NO_CONTENT, id))
+                    .map_err(AppError::from);
+                outcomes.push((index, outcome));
+            }
+        }
+    }
+
+    if !creates.is_empty() {
+        let inserted = recipes.create_many(&creates, ordered).await;
+
+        for ((index, recipe), outcome) in indexes.into_iter().zip(creates).zip(inserted) {
+            let outcome = match outcome {
+                InsertOutcome::Inserted => Ok((StatusCode::CREATED, recipe.id)),
+                InsertOutcome::Failed(e) => Err(e.into()),
+                InsertOutcome::NotExecuted => Err(AppError::FailedDependency)
+            };
+            outcomes.push((index, outcome));
+        }
+    }
+
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_support::{fixture_json, repository};
+
+    fn payload(ordered: bool, operations: Value) -> BulkPayload {
+        serde_json::from_value(json!({ "ordered": ordered, "allow_duplicate": true, "operations": operations })).unwrap()
+    }
+
+    fn statuses(result: &BulkResult) -> Vec<u16> {
+        result.results.iter().map(|result| result.status).collect()
+    }
+
+    #[tokio::test]
+    async fn ordered_create_can_reuse_an_id_deleted_before_it() {
+        let recipes = repository();
+        let operations = json!([
+            { "op": "delete", "id": "716429" },
+            { "op": "create", "recipe": fixture_json("716429") },
+            { "op": "create", "recipe": fixture_json("716429") },
+            { "op": "delete", "id": "715594" }
+        ]);
+
+        let result = execute(&recipes, payload(true, operations)).await.unwrap();
+        assert_eq!(statuses(&result), [204, 201, 409, 424]);
+        assert!(recipes.read("716429").await.unwrap().is_some());
+        assert!(recipes.read("715594").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn unordered_creates_report_taken_ids_one_by_one() {
+        let recipes = repository();
+        let mut fresh = fixture_json("716429");
+        fresh["id"] = json!("new");
+        let operations = json!([
+            { "op": "create", "recipe": fixture_json("782601") },
+            { "op": "create", "recipe": fresh },
+            { "op": "create", "recipe": fresh }
+        ]);
+
+        let result = execute(&recipes, payload(false, operations)).await.unwrap();
+        assert_eq!(statuses(&result), [409, 201, 409]);
+        assert_eq!((result.succeeded, result.failed), (1, 2));
+    }
+}
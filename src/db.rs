@@ -1,21 +1,15 @@
-use std::env;
-use thiserror::Error;
+use std::{collections::{HashMap, HashSet}, env};
 use dotenv::dotenv;
-use mongodb::{bson::{self, doc, Document}, options::{ClientOptions, UpdateOptions}, Client, Collection, Database};
+use mongodb::{bson::{self, doc, Bson, Document, Regex}, error::{BulkWriteFailure, ErrorKind, WriteFailure}, options::{ClientOptions, FindOneAndUpdateOptions, FindOptions, IndexOptions, InsertManyOptions, ReturnDocument, UpdateOptions}, Client, IndexModel, Collection, Database};
 use futures::stream::StreamExt;
 
-use crate::models::{Filters, Recipe};
+use crate::{allergens, filter_builder::{build_filter, is_text_search}, models::{Filters, Recipe, RecipeSource}, pagination::{Cursor, Direction}, repository::{InsertOutcome, KeysetPage, Page, RecipeError}, sort::{sort_document, SortField, SortKey}};
 
 
-#[derive(Error, Debug)]
-pub enum RecipeError {
-    #[error("Database error")]
-    DatabaseError(#[from] mongodb::error::Error),
-    #[error("Serialization error")]
-    SerializationError(#[from] bson::de::Error),
-    #[error("Unknown error")]
-    Unknown,
-}
+const DUPLICATE_KEY: i32 = 11000;
+const NAMESPACE_NOT_FOUND: i32 = 26;
+
+pub const UNIQUE_ID_INDEX: &str = "id_1";
 
 pub async fn connect() -> Database {
     dotenv().ok();
@@ -28,15 +22,184 @@ pub async fn connect() -> Database {
     client.database("Recipes")
 }
 
+// What `ensure_indexes` did with each index, by name.
+#[derive(Debug, Default)]
+pub struct IndexReport {
+    pub created: Vec<String>,
+    pub existing: Vec<String>,
+    pub failed: Vec<(String, mongodb::error::Error)>
+}
 
-pub async fn create_recipe(collection: &Collection<Recipe>, recipe: &Recipe) -> mongodb::error::Result<()> {
-    let filter = doc! { "id": recipe.id.clone() };
-    let update = doc! { "$set": recipe.to_document() };
+// The name MongoDB gives an index unless told otherwise, e.g. `diets_1_ready_in_minutes_1`.
+fn index_name(index: &IndexModel) -> String {
+    index.options.as_ref().and_then(|options| options.name.clone()).unwrap_or_else(|| {
+        index.keys.iter().map(|(key, value)| format!("{}_{}", key, value)).collect::<Vec<_>>().join("_")
+    })
+}
 
-    collection.update_one(filter, update, Some(UpdateOptions::builder().upsert(true).build())).await?;
+fn recipe_indexes() -> Vec<IndexModel> {
+    let unique_id = IndexModel::builder()
+        .keys(doc! { "id": 1 })
+        .options(IndexOptions::builder().name(UNIQUE_ID_INDEX.to_string()).unique(true).build())
+        .build();
+
+    let sort_indexes = [
+        SortField::ReadyTime,
+        SortField::Servings,
+        SortField::Calories,
+        SortField::HealthScore,
+        SortField::Title
+    ].iter().map(|field| IndexModel::builder().keys(doc! { field.document_field(): 1 }).build());
+
+    // MongoDB cannot index two arrays in one compound index, so each tag list gets its own, followed by the
+    // range filters that usually come with it.
+    let tag_indexes = ["diets", "cuisines", "dish_types"].into_iter()
+        .map(|field| IndexModel::builder().keys(doc! { field: 1, "ready_in_minutes": 1, "servings": 1 }).build());
+
+    // Multikey indexes serving the `$elemMatch` on a nutrient or property name and its amount.
+    let element_indexes = ["nutrition.nutrients", "nutrition.properties"].into_iter()
+        .map(|field| IndexModel::builder().keys(doc! { format!("{}.name", field): 1, format!("{}.amount", field): 1 }).build());
+
+    // A collection can only have one text index, so every searchable field lives in it, weighted by how telling a match is.
+    let text_index = IndexModel::builder()
+        .keys(doc! {
+            "title": "text",
+            "summary": "text",
+            "ingredients.name": "text",
+            "instructions.step": "text"
+        })
+        .options(IndexOptions::builder()
+            .name("recipe_text".to_string())
+            .weights(doc! { "title": 10, "ingredients.name": 5, "summary": 2, "instructions.step": 1 })
+            .build())
+        .build();
+
+    let fingerprint_index = IndexModel::builder().keys(doc! { "fingerprint": 1 }).build();
+
+    [unique_id].into_iter()
+        .chain(sort_indexes)
+        .chain(tag_indexes)
+        .chain(element_indexes)
+        .chain([text_index, fingerprint_index])
+        .collect()
+}
+
+// Safe to run on every start: indexes that already exist are left alone. Each index is created on its own, so one
+// that cannot be built (e.g. the unique `id` index over existing duplicates) doesn't hold back the rest.
+pub async fn ensure_indexes(collection: &Collection<Recipe>) -> mongodb::error::Result<IndexReport> {
+    let existing: HashSet<String> = match collection.list_index_names().await {
+        Ok(names) => names.into_iter().collect(),
+        Err(e) if matches!(*e.kind, ErrorKind::Command(ref error) if error.code == NAMESPACE_NOT_FOUND) => HashSet::new(),
+        Err(e) => return Err(e)
+    };
+
+    let mut report = IndexReport::default();
+
+    for index in recipe_indexes() {
+        let name = index_name(&index);
+
+        if existing.contains(&name) {
+            report.existing.push(name);
+            continue;
+        }
+
+        match collection.create_index(index, None).await {
+            Ok(_) => report.created.push(name),
+            Err(e) => report.failed.push((name, e))
+        }
+    }
+
+    Ok(report)
+}
+
+// Only ever overwrites imported recipes. A user's recipe under the same id leaves the filter without a match, and
+// the insert that the upsert falls back to then trips the unique `id` index, which startup refuses to go without.
+pub async fn upsert_recipe(collection: &Collection<Recipe>, recipe: &Recipe) -> Result<(), RecipeError> {
+    let filter = doc! { "id": recipe.id.clone(), "source": { "$ne": RecipeSource::User.name() } };
+    let mut document = recipe.to_document();
+    document.insert("source", recipe.source.name());
+    let update = doc! { "$set": document, "$inc": { "version": 1 } };
+
+    collection.update_one(filter, update, Some(UpdateOptions::builder().upsert(true).build())).await.map_err(|e| {
+        match *e.kind {
+            ErrorKind::Write(WriteFailure::WriteError(ref error)) if error.code == DUPLICATE_KEY => RecipeError::AlreadyExists(recipe.id.clone()),
+            _ => RecipeError::DatabaseError(e)
+        }
+    })?;
     Ok(())
 }
 
+fn new_document(recipe: &Recipe) -> Document {
+    let mut document = recipe.to_document();
+    document.insert("source", recipe.source.name());
+    document.insert("version", 1_i64);
+    document
+}
+
+// Never overwrites: an existing id, whether found up front or by a concurrent insert, is an error.
+pub async fn insert_recipe(collection: &Collection<Recipe>, recipe: &Recipe) -> Result<Recipe, RecipeError> {
+    if collection.count_documents(doc! { "id": recipe.id.clone() }, None).await? > 0 {
+        return Err(RecipeError::AlreadyExists(recipe.id.clone()));
+    }
+
+    let result = collection.clone_with_type::<Document>().insert_one(new_document(recipe), None).await.map_err(|e| {
+        match *e.kind {
+            ErrorKind::Write(WriteFailure::WriteError(ref error)) if error.code == DUPLICATE_KEY => RecipeError::AlreadyExists(recipe.id.clone()),
+            _ => RecipeError::DatabaseError(e)
+        }
+    })?;
+
+    // What `to_document` stored, not what the client sent.
+    Ok(Recipe { _id: result.inserted_id.as_object_id(), allergens: allergens::detect(recipe), version: 1, ..recipe.clone() })
+}
+
+// One `insert_many` for the whole batch. An ordered insert stops at its first failure, so everything after it
+// is reported as not executed; an unordered one attempts every document.
+pub async fn insert_recipes(collection: &Collection<Recipe>, recipes: &[Recipe], ordered: bool) -> Vec<InsertOutcome> {
+    let documents: Vec<Document> = recipes.iter().map(new_document).collect();
+
+    let options = InsertManyOptions::builder().ordered(ordered).build();
+
+    let error = match collection.clone_with_type::<Document>().insert_many(documents, options).await {
+        Ok(_) => return recipes.iter().map(|_| InsertOutcome::Inserted).collect(),
+        Err(e) => e
+    };
+
+    let ErrorKind::BulkWrite(BulkWriteFailure { write_errors: Some(ref write_errors), .. }) = *error.kind else {
+        let message = error.to_string();
+        return recipes.iter().map(|_| InsertOutcome::Failed(RecipeError::WriteFailed(message.clone()))).collect();
+    };
+
+    let first_failure = write_errors.iter().map(|write_error| write_error.index).min().unwrap_or(recipes.len());
+
+    recipes.iter().enumerate().map(|(index, recipe)| {
+        match write_errors.iter().find(|write_error| write_error.index == index) {
+            Some(write_error) if write_error.code == DUPLICATE_KEY => InsertOutcome::Failed(RecipeError::AlreadyExists(recipe.id.clone())),
+            Some(write_error) => InsertOutcome::Failed(RecipeError::WriteFailed(write_error.message.clone())),
+            None if ordered && index > first_failure => InsertOutcome::NotExecuted,
+            None => InsertOutcome::Inserted
+        }
+    }).collect()
+}
+
+// Maps each given fingerprint that is already stored to the id of a recipe carrying it. Legacy documents
+// get a fingerprint the next time they are written.
+pub async fn find_duplicates(collection: &Collection<Recipe>, fingerprints: &[String]) -> mongodb::error::Result<HashMap<String, String>> {
+    let options = FindOptions::builder().projection(doc! { "id": 1, "fingerprint": 1 }).build();
+    let mut cursor = collection.clone_with_type::<Document>().find(doc! { "fingerprint": { "$in": fingerprints } }, options).await?;
+
+    let mut duplicates = HashMap::new();
+
+    while let Some(document) = cursor.next().await {
+        let document = document?;
+        if let (Ok(fingerprint), Ok(id)) = (document.get_str("fingerprint"), document.get_str("id")) {
+            duplicates.insert(fingerprint.to_string(), id.to_string());
+        }
+    }
+
+    Ok(duplicates)
+}
+
 pub async fn read_recipe(collection: &Collection<Recipe>, id: &str) -> mongodb::error::Result<Option<Recipe>> {
     let filter = doc! { "id": id };
     if let Some(doc) = collection.find_one(filter, None).await? {
@@ -46,72 +209,75 @@ pub async fn read_recipe(collection: &Collection<Recipe>, id: &str) -> mongodb::
     }
 }
 
-pub async fn filter_recipes(collection: &Collection<Recipe>, filters: Filters, page: usize) -> Result<Vec<Recipe>, RecipeError> {
-    let mut filter = doc! {};
-
-    if !filters.query.is_empty() {
-        filter.insert("$or", vec![
-            doc! { "title": { "$regex": filters.query.clone() } },
-            doc! { "summary": { "$regex": filters.query.clone() } }
-        ]);
-    }
-
-    if !filters.diets.is_empty() {
-        filter.insert("diets", doc! { "$in": filters.diets });
+pub async fn filter_recipes(collection: &Collection<Recipe>, filters: Filters, sort: &[SortKey], page: usize, page_size: usize) -> Result<Page, RecipeError> {
+    let sort = if sort.is_empty() && is_text_search(&filters) {
+        doc! { "score": { "$meta": "textScore" }, "_id": 1 }
+    } else {
+        sort_document(sort)
     };
 
-    if !filters.cuisines.is_empty() {
-        filter.insert("cuisines", doc! { "$in": filters.cuisines });
-    };
+    find_page(collection, build_filter(filters), sort, page, page_size).await
+}
 
-    if !filters.dish_types.is_empty() {
-        filter.insert("dish_types", doc! { "$in": filters.dish_types });
-    };
+pub async fn count_recipes(collection: &Collection<Recipe>, filters: Filters) -> Result<u64, RecipeError> {
+    Ok(collection.count_documents(build_filter(filters), None).await?)
+}
 
-    if filters.min_servings > 0 {
-        filter.insert("servings", doc! { "$gte": filters.min_servings });
-    };
+pub async fn filter_recipes_keyset(collection: &Collection<Recipe>, filters: Filters, cursor: Option<&Cursor>, page_size: usize) -> Result<KeysetPage, RecipeError> {
+    find_keyset_page(collection, build_filter(filters), cursor, page_size).await
+}
 
-    if filters.max_calories > 0.0 {
-        filter.insert("nutrition.nutrients", doc! { 
-            "$elemMatch": { "name": "Fats", "amount": { "$lte": filters.max_calories } }
-        });
+// Recipes using at least one of the given words in an ingredient name, those leaving the fewest ingredients
+// unmatched first so that `limit` keeps the most promising ones. Callers do the precise matching and ranking.
+pub async fn find_by_ingredient_terms(collection: &Collection<Recipe>, terms: &[String], limit: i64) -> Result<Vec<Recipe>, RecipeError> {
+    // Every pattern starts with `\b`, so none of them is mistaken for a field path inside the pipeline.
+    let patterns: Vec<String> = terms.iter().map(|term| format!(r"\b{}", regex::escape(term))).collect();
+
+    let regexes: Vec<Bson> = patterns.iter()
+        .map(|pattern| Bson::RegularExpression(Regex { pattern: pattern.clone(), options: "i".to_string() }))
+        .collect();
+
+    let matched = doc! {
+        "$size": { "$filter": {
+            "input": "$ingredients.name",
+            "as": "name",
+            "cond": { "$anyElementTrue": [{ "$map": {
+                "input": patterns,
+                "as": "pattern",
+                "in": { "$regexMatch": { "input": "$$name", "regex": "$$pattern", "options": "i" } }
+            } }] }
+        } }
     };
 
-    if filters.max_fats > 0.0 {
-        filter.insert("nutrition.nutrients", doc! { 
-            "$elemMatch": { "name": "Fats", "amount": { "$lte": filters.max_fats } }
-        });
-    };
+    let pipeline = vec![
+        doc! { "$match": { "ingredients.name": { "$in": regexes } } },
+        doc! { "$addFields": { "_matched": matched } },
+        doc! { "$addFields": { "_unmatched": { "$subtract": [{ "$size": "$ingredients" }, "$_matched"] } } },
+        doc! { "$sort": { "_unmatched": 1, "_matched": -1, "_id": 1 } },
+        doc! { "$limit": limit },
+        doc! { "$project": { "_matched": 0, "_unmatched": 0 } }
+    ];
 
-    if filters.max_carbs > 0.0 {
-        filter.insert("nutrition.nutrients", doc! { 
-            "$elemMatch": { "name": "Carbohydrates", "amount": { "$lte": filters.max_carbs } }
-        });
-    };
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    let mut recipes: Vec<Recipe> = Vec::new();
 
-    if filters.max_glycemic_index > 0.0 {
-        filter.insert("nutrition.properties", doc! {
-            "$elemMatch": { "name": "Glycemic Index", "amount": { "$lte": filters.max_glycemic_index } }
-        });
-    };
+    while let Some(document) = cursor.next().await {
+        recipes.push(bson::from_document(document?)?);
+    }
 
-    if filters.healthy {
-        filter.insert("nutrition.properties", doc! {
-            "$elemMatch": {
-                "name": "Nutrition Score",
-                "amount": { "$gte": 60.0 }
-            }
-        });
-    };
+    Ok(recipes)
+}
 
-    let skip = (page - 1) * 15;
+async fn find_page(collection: &Collection<Recipe>, filter: Document, sort: Document, page: usize, page_size: usize) -> Result<Page, RecipeError> {
+    let total_items = collection.count_documents(filter.clone(), None).await?;
 
-    let mut cursor = collection
-        .find(filter, None)
-        .await?
-        .skip(skip)
-        .take(15);
+    let options = FindOptions::builder()
+        .sort(sort)
+        .skip(((page - 1) * page_size) as u64)
+        .limit(page_size as i64)
+        .build();
+
+    let mut cursor = collection.find(filter, options).await?;
 
     let mut recipes: Vec<Recipe> = Vec::new();
 
@@ -122,108 +288,106 @@ pub async fn filter_recipes(collection: &Collection<Recipe>, filters: Filters, p
         }
     };
 
-    Ok(recipes)
+    Ok(Page { recipes, total_items })
 }
 
-pub async fn list_recipes(collection: &Collection<Recipe>, limit: Option<usize>, page: Option<usize>) -> Result<Vec<Recipe>, RecipeError> {
+// Seeks on `_id` instead of skipping, so deep pages stay cheap and concurrent inserts don't shift them.
+async fn find_keyset_page(collection: &Collection<Recipe>, mut filter: Document, cursor: Option<&Cursor>, page_size: usize) -> Result<KeysetPage, RecipeError> {
+    let direction = cursor.map_or(Direction::Next, |cursor| cursor.direction);
+
+    if let Some(cursor) = cursor {
+        let operator = match cursor.direction {
+            Direction::Next => "$gt",
+            Direction::Prev => "$lt"
+        };
+        let mut range = Document::new();
+        range.insert(operator, cursor.id);
+        filter.insert("_id", range);
+    }
 
-    if limit.is_some() {
-        let mut cursor = collection
-            .find(None, None)
-            .await?
-            .take(limit.unwrap());
+    let sort_order = match direction {
+        Direction::Next => 1,
+        Direction::Prev => -1
+    };
 
-        let mut recipes: Vec<Recipe> = Vec::new();
-    
-        while let Some(result) = cursor.next().await {
-            match result {
-                Ok(recipe) => {
-                    recipes.push(recipe)
-                },
-                Err(e) => return Err(RecipeError::DatabaseError(e))
-            }
-        }
-    
-        Ok(recipes)
-    } else if page.is_some() {
-        let skip = (page.unwrap() - 1) * 15;
-
-        let mut cursor = collection
-            .find(None, None)
-            .await?
-            .skip(skip)
-            .take(15);
-
-        let mut recipes: Vec<Recipe> = Vec::new();
-    
-        while let Some(result) = cursor.next().await {
-            match result {
-                Ok(recipe) => {
-                    recipes.push(recipe)
-                },
-                Err(e) => return Err(RecipeError::DatabaseError(e))
-            }
-        }
-    
-        Ok(recipes)
-    } else {
-        Err(RecipeError::Unknown)
-    }
-    
-}
+    // One extra document tells us whether another page exists in this direction.
+    let options = FindOptions::builder()
+        .sort(doc! { "_id": sort_order })
+        .limit(page_size as i64 + 1)
+        .build();
 
-pub async fn update_recipe(collection: &Collection<Recipe>, id: &str, updated_recipe: Document) -> mongodb::error::Result<()> {
-    let filter = doc! { "id": id };
-    let update = doc! { "$set": updated_recipe };
-    collection.update_one(filter, update, None).await?;
-    Ok(())
-}
+    let mut cursor = collection.find(filter, options).await?;
 
-pub async fn delete_recipe(collection: &Collection<Recipe>, id: &str) -> mongodb::error::Result<()> {
-    let filter = doc! { "id": id };
-    collection.delete_one(filter, None).await?;
-    Ok(())
-}
+    let mut recipes: Vec<Recipe> = Vec::new();
 
+    while let Some(result) = cursor.next().await {
+        match result {
+            Ok(recipe) => { recipes.push(recipe) },
+            Err(e) => return Err(RecipeError::DatabaseError(e))
+        }
+    };
 
+    let has_more = recipes.len() > page_size;
+    recipes.truncate(page_size);
 
+    if direction == Direction::Prev {
+        recipes.reverse();
+    }
 
+    Ok(KeysetPage { recipes, has_more })
+}
 
+// `versions` comes from an `If-Match` header; `None` means any version will do.
+fn version_filter(id: &str, versions: Option<&[i64]>) -> Document {
+    let mut filter = doc! { "id": id };
 
-// use sqlx::{ migrate::MigrateDatabase, Sqlite, SqlitePool };
+    // Recipes stored before versioning have no `version` field, which `null` matches.
+    if let Some(versions) = versions {
+        let versions: Vec<Bson> = versions.iter()
+            .map(|version| if *version == 0 { Bson::Null } else { Bson::Int64(*version) })
+            .collect();
+        filter.insert("version", doc! { "$in": versions });
+    }
+    filter
+}
 
-// const DB_URL: &str = "sqlite://sqlite.db";
+async fn missing(collection: &Collection<Recipe>, id: &str, versions: Option<&[i64]>) -> RecipeError {
+    if versions.is_none() {
+        return RecipeError::NotFound(id.to_string());
+    }
 
-// pub async fn initialize_database() -> Result<SqlitePool, sqlx::Error> {
+    match collection.count_documents(doc! { "id": id }, None).await {
+        Ok(0) => RecipeError::NotFound(id.to_string()),
+        Ok(_) => RecipeError::VersionMismatch(id.to_string()),
+        Err(e) => RecipeError::DatabaseError(e)
+    }
+}
 
-//     let db = SqlitePool::connect(DB_URL).await.unwrap();
+async fn apply_update(collection: &Collection<Recipe>, id: &str, mut update: Document, versions: Option<&[i64]>) -> Result<Recipe, RecipeError> {
+    update.insert("$inc", doc! { "version": 1 });
 
-//     if !Sqlite::database_exists(DB_URL).await.unwrap_or(false) {
-//         println!("Creating database {}", DB_URL);
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
 
-//         match Sqlite::create_database(DB_URL).await {
-//             Ok(_) => println!("Database succesfully created"),
-//             Err(error) => panic!("Error: {}", error),
-//         }
-    
-//     } else {
-//         println!("Database already exists!");
-        
-//         let result = sqlx::query(
-//             "CREATE TABLE IF NOT EXISTS feed (id TEXT PRIMARY KEY NOT NULL, low_res_url TEXT, high_res_url TEXT, caption TEXT, permalink TEXT, timestamp TEXT)"
-//         ).execute(&db).await;
+    match collection.find_one_and_update(version_filter(id, versions), update, options).await? {
+        Some(recipe) => Ok(recipe),
+        None => Err(missing(collection, id, versions).await)
+    }
+}
 
-//         println!("Create user table result: {:?}", result);
-//     }
+pub async fn update_recipe(collection: &Collection<Recipe>, id: &str, updated_recipe: Document, versions: Option<&[i64]>) -> Result<Recipe, RecipeError> {
+    apply_update(collection, id, doc! { "$set": updated_recipe }, versions).await
+}
 
-//     Ok(db)
-    
-// }
+// Unlike `update_recipe`, takes a full update document so patches can `$unset` and `$push` as well as `$set`.
+pub async fn patch_recipe(collection: &Collection<Recipe>, id: &str, update: Document, versions: Option<&[i64]>) -> Result<Recipe, RecipeError> {
+    apply_update(collection, id, update, versions).await
+}
 
-// // pub async fn delete_all_records(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-// //     sqlx::query("DELETE FROM feed")
-// //         .execute(pool)
-// //         .await?;
+pub async fn delete_recipe(collection: &Collection<Recipe>, id: &str, versions: Option<&[i64]>) -> Result<(), RecipeError> {
+    let result = collection.delete_one(version_filter(id, versions), None).await?;
 
-// //     Ok(())
-// // }
\ No newline at end of file
+    if result.deleted_count == 0 {
+        return Err(missing(collection, id, versions).await);
+    }
+    Ok(())
+}
@@ -0,0 +1,252 @@
+use std::{future::{ready, Ready}, rc::Rc};
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    http::{header::{HeaderName, HeaderValue, ALLOW}, StatusCode},
+    web,
+    Error,
+    HttpRequest,
+    HttpResponse,
+    ResponseError,
+    Route
+};
+use futures::future::LocalBoxFuture;
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{auth::AuthError, repository::RecipeError, validation::FieldError};
+
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+#[derive(Error, Debug)]
+pub enum AppError {
+    #[error("A database error occurred")]
+    Database(String),
+    #[error("{message}")]
+    Validation { message: String, details: Option<Value> },
+    #[error("The payload failed validation")]
+    Unprocessable(Vec<FieldError>),
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("Method not allowed, expected one of {}", .0.join(", "))]
+    MethodNotAllowed(&'static [&'static str]),
+    #[error("A valid API key is required")]
+    Unauthorized,
+    #[error("This API key lacks the required scope")]
+    Forbidden,
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    PreconditionFailed(String),
+    #[error("Not executed because an earlier operation failed")]
+    FailedDependency,
+    #[error("Rate limit exceeded")]
+    RateLimited,
+    #[error("Unsupported content type {0}")]
+    UnsupportedMediaType(String),
+    #[error("Upstream service error: {0}")]
+    Upstream(String)
+}
+
+#[derive(Serialize)]
+pub struct ErrorBody {
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>
+}
+
+impl AppError {
+    pub fn validation(message: impl Into<String>) -> Self {
+        AppError::Validation { message: message.into(), details: None }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::Database(_) => "database_error",
+            AppError::Validation { .. } => "validation_error",
+            AppError::Unprocessable(_) => "unprocessable_entity",
+            AppError::NotFound(_) => "not_found",
+            AppError::MethodNotAllowed(_) => "method_not_allowed",
+            AppError::Unauthorized => "unauthorized",
+            AppError::Forbidden => "forbidden",
+            AppError::Conflict(_) => "conflict",
+            AppError::PreconditionFailed(_) => "precondition_failed",
+            AppError::FailedDependency => "failed_dependency",
+            AppError::RateLimited => "rate_limited",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::Upstream(_) => "upstream_error"
+        }
+    }
+
+    // Also used for the per-item errors of bulk requests, which leave out the request id.
+    pub fn body(&self, request_id: Option<String>) -> ErrorBody {
+        if let AppError::Database(cause) = self {
+            log::error!("Database error: {}", cause);
+        }
+
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            details: self.details(),
+            request_id
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            AppError::Validation { details, .. } => details.clone(),
+            AppError::Unprocessable(errors) => serde_json::to_value(errors).ok(),
+            _ => None
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Validation { .. } => StatusCode::BAD_REQUEST,
+            AppError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden => StatusCode::FORBIDDEN,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::FailedDependency => StatusCode::FAILED_DEPENDENCY,
+            AppError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::Upstream(_) => StatusCode::BAD_GATEWAY
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+
+        if let AppError::MethodNotAllowed(allowed) = self {
+            response.insert_header((ALLOW, allowed.join(", ")));
+        }
+
+        response.json(self.body(REQUEST_ID.try_with(|id| id.clone()).ok()))
+    }
+}
+
+impl From<mongodb::error::Error> for AppError {
+    fn from(e: mongodb::error::Error) -> Self {
+        AppError::Database(e.to_string())
+    }
+}
+
+impl From<RecipeError> for AppError {
+    fn from(e: RecipeError) -> Self {
+        match e {
+            RecipeError::DatabaseError(e) => AppError::Database(e.to_string()),
+            RecipeError::SerializationError(e) => AppError::Database(e.to_string()),
+            RecipeError::SqliteError(e) => AppError::Database(e.to_string()),
+            RecipeError::NotFound(id) => AppError::NotFound(format!("Recipe {}", id)),
+            RecipeError::WriteFailed(message) => AppError::Database(message),
+            RecipeError::AlreadyExists(id) => AppError::Conflict(format!("Recipe {} already exists", id)),
+            RecipeError::VersionMismatch(id) => AppError::PreconditionFailed(format!("Recipe {} has been modified since the given version", id))
+        }
+    }
+}
+
+impl From<AuthError> for AppError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::DatabaseError(e) => AppError::Database(e.to_string()),
+            AuthError::SqliteError(e) => AppError::Database(e.to_string())
+        }
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        AppError::Upstream(e.to_string())
+    }
+}
+
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> Error {
+    AppError::validation(format!("Invalid JSON payload: {}", err)).into()
+}
+
+pub fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> Error {
+    AppError::validation(format!("Invalid query string: {}", err)).into()
+}
+
+pub fn path_error_handler(err: PathError, _req: &HttpRequest) -> Error {
+    AppError::validation(format!("Invalid path parameter: {}", err)).into()
+}
+
+// Stand-ins for actix's empty 404 and 405 responses, so every error has the same JSON body.
+pub async fn not_found(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    Err(AppError::NotFound(format!("Route {} {}", req.method(), req.path())))
+}
+
+pub fn method_not_allowed(allowed: &'static [&'static str]) -> Route {
+    web::to(move || async move { Err::<HttpResponse, _>(AppError::MethodNotAllowed(allowed)) })
+}
+
+// Tags each request with an id (the caller's `X-Request-Id` if sane) that error bodies and the response header echo back.
+pub struct RequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct RequestIdMiddleware<S> {
+    service: Rc<S>
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        let request_id = req.headers()
+            .get(X_REQUEST_ID)
+            .and_then(|header_value| header_value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+
+        Box::pin(REQUEST_ID.scope(request_id.clone(), async move {
+            let mut res = service.call(req).await?;
+
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(X_REQUEST_ID, value);
+            }
+
+            Ok(res)
+        }))
+    }
+}
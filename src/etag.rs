@@ -0,0 +1,27 @@
+use actix_web::{http::header::{EntityTag, IfMatch, IfNoneMatch}, HttpMessage, HttpRequest};
+
+use crate::models::Recipe;
+
+pub fn entity_tag(recipe: &Recipe) -> EntityTag {
+    EntityTag::new_strong(recipe.version.to_string())
+}
+
+// The versions an `If-Match` header allows, or `None` when there is no header or it is `*`.
+// Weak tags never match here, as RFC 9110 requires strong comparison for `If-Match`.
+pub fn expected_versions(req: &HttpRequest) -> Option<Vec<i64>> {
+    match req.get_header::<IfMatch>()? {
+        IfMatch::Any => None,
+        IfMatch::Items(tags) => Some(tags.iter()
+            .filter(|tag| !tag.weak)
+            .filter_map(|tag| tag.tag().parse().ok())
+            .collect())
+    }
+}
+
+pub fn not_modified(req: &HttpRequest, recipe: &Recipe) -> bool {
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&entity_tag(recipe))),
+        None => false
+    }
+}
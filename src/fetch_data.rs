@@ -1,20 +1,20 @@
-use mongodb::{Collection, Database};
 use serde::{Serialize, Deserialize};
 use reqwest::{self, Client};
 
-use crate::{api_structs::APIRecipe, db::create_recipe, models::Recipe};
+use crate::{api_structs::APIRecipe, errors::AppError, models::Recipe, repository::RecipeRepository};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct APIResponse {
     pub results: Vec<APIRecipe>
 }
 
-pub async fn fetch_and_store_recipes(api_key: &str, database: &Database) {
-    let recipes: &[Recipe] = &fetch_recipes_from_api(api_key).await.expect("Failed");
-    store_recipes_in_db(recipes, database).await;
+pub async fn fetch_and_store_recipes(api_key: &str, repository: &dyn RecipeRepository) -> Result<(), AppError> {
+    let recipes: &[Recipe] = &fetch_recipes_from_api(api_key).await?;
+    store_recipes_in_db(recipes, repository).await;
+    Ok(())
 }
 
-async fn fetch_recipes_from_api(api_key: &str) -> Result<Vec<Recipe>, reqwest::Error> {
+async fn fetch_recipes_from_api(api_key: &str) -> Result<Vec<Recipe>, AppError> {
     let query_arr = vec!["pasta", "mushroom", "stew", "sandwich", "noodles", "soup", "shake", "smoothie", "sweet", "maggi"];
     
     let client = Client::new();
@@ -50,10 +50,10 @@ async fn fetch_recipes_from_api(api_key: &str) -> Result<Vec<Recipe>, reqwest::E
     Ok(recipes)
 }
 
-async fn store_recipes_in_db(recipes: &[Recipe], database: &Database) {
-    let collection: Collection<Recipe> = database.collection("Recipes");
-
+async fn store_recipes_in_db(recipes: &[Recipe], repository: &dyn RecipeRepository) {
     for recipe in recipes {
-        let _ = create_recipe(&collection, recipe).await;
+        if let Err(e) = repository.upsert(recipe).await {
+            log::warn!("Skipping imported recipe {}: {}", recipe.id, e);
+        }
     }
 }
\ No newline at end of file
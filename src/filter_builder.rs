@@ -0,0 +1,364 @@
+use mongodb::bson::{doc, Bson, Document};
+use serde_json::{json, Map, Value};
+
+use crate::{allergens::Intolerance, errors::AppError, models::{Filters, SearchMode}};
+
+pub const HEALTHY_NUTRITION_SCORE: f32 = 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagList {
+    Diets,
+    Cuisines,
+    DishTypes
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Count {
+    ReadyInMinutes,
+    Servings
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementList {
+    Nutrients,
+    Properties
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    DairyFree,
+    GlutenFree
+}
+
+// What a set of `Filters` asks of a recipe, whatever the backend. A recipe matches if every constraint holds,
+// and each backend only translates constraints into its own query language.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    // Relevance-ranked search for any of these words, already stripped of search syntax.
+    Text(String),
+    // Case-insensitive match on the start of a word in the title or an ingredient name.
+    Prefix(String),
+    AnyTag(TagList, Vec<String>),
+    Range(Count, Option<i64>, Option<i64>),
+    // Some nutrient or property with this name has an amount within the bounds.
+    Element(ElementList, String, Option<f32>, Option<f32>),
+    Flag(Flag),
+    NoAllergens(Vec<Intolerance>)
+}
+
+// Constraints that would let everything through (no bounds, no values) are left out.
+pub fn constraints(filters: &Filters) -> Vec<Constraint> {
+    let mut constraints = Vec::new();
+
+    match (filters.query.as_deref().map(str::trim).filter(|query| !query.is_empty()), filters.search_mode) {
+        (Some(query), SearchMode::Text) => {
+            let terms = escape_text_search(query);
+            if !terms.is_empty() {
+                constraints.push(Constraint::Text(terms));
+            }
+        },
+        (Some(query), SearchMode::Prefix) => constraints.push(Constraint::Prefix(query.to_string())),
+        (None, _) => {}
+    }
+
+    for (list, values) in [(TagList::Diets, &filters.diets), (TagList::Cuisines, &filters.cuisines), (TagList::DishTypes, &filters.dish_types)] {
+        if !values.is_empty() {
+            constraints.push(Constraint::AnyTag(list, values.clone()));
+        }
+    }
+
+    let ranges = [
+        (Count::ReadyInMinutes, filters.min_ready_time, filters.max_ready_time),
+        (Count::Servings, filters.min_servings, filters.max_servings)
+    ];
+    let elements = [
+        (ElementList::Nutrients, "Calories", None, filters.max_calories),
+        (ElementList::Nutrients, "Fat", None, filters.max_fats),
+        (ElementList::Nutrients, "Carbohydrates", None, filters.max_carbs),
+        (ElementList::Properties, "Glycemic Index", None, filters.max_glycemic_index),
+        (ElementList::Properties, "Nutrition Score", filters.healthy.then_some(HEALTHY_NUTRITION_SCORE), None)
+    ];
+
+    constraints.extend(ranges.into_iter()
+        .filter(|(_, min, max)| min.is_some() || max.is_some())
+        .map(|(count, min, max)| Constraint::Range(count, min, max)));
+
+    constraints.extend(elements.into_iter()
+        .chain(filters.nutrients.iter().map(|range| (ElementList::Nutrients, range.name.as_str(), range.min, range.max)))
+        .filter(|(_, _, min, max)| min.is_some() || max.is_some())
+        .map(|(list, name, min, max)| Constraint::Element(list, name.to_string(), min, max)));
+
+    // The flags also cover recipes stored before allergens were computed.
+    constraints.extend(filters.intolerances.iter().filter_map(|intolerance| match intolerance {
+        Intolerance::Dairy => Some(Constraint::Flag(Flag::DairyFree)),
+        Intolerance::Gluten => Some(Constraint::Flag(Flag::GlutenFree)),
+        _ => None
+    }));
+
+    if !filters.intolerances.is_empty() {
+        constraints.push(Constraint::NoAllergens(filters.intolerances.clone()));
+    }
+
+    constraints
+}
+
+// Every constraint becomes its own clause and the clauses are joined with `$and`, so two
+// constraints on the same array (e.g. calories and carbs on `nutrition.nutrients`) both apply.
+#[derive(Debug, Default)]
+pub struct FilterBuilder {
+    clauses: Vec<Document>
+}
+
+impl FilterBuilder {
+    pub fn new() -> Self {
+        FilterBuilder::default()
+    }
+
+    pub fn text_search(mut self, terms: &str) -> Self {
+        self.clauses.push(doc! { "$text": { "$search": terms } });
+        self
+    }
+
+    pub fn word_prefix(mut self, term: &str) -> Self {
+        let pattern = format!(r"\b{}", regex::escape(term));
+        self.clauses.push(doc! {
+            "$or": [
+                { "title": { "$regex": pattern.as_str(), "$options": "i" } },
+                { "ingredients.name": { "$regex": pattern.as_str(), "$options": "i" } }
+            ]
+        });
+        self
+    }
+
+    pub fn any_of(mut self, field: &str, values: Vec<String>) -> Self {
+        if !values.is_empty() {
+            self.clauses.push(doc! { field: { "$in": values } });
+        }
+        self
+    }
+
+    pub fn none_of(mut self, field: &str, values: Vec<String>) -> Self {
+        if !values.is_empty() {
+            self.clauses.push(doc! { field: { "$nin": values } });
+        }
+        self
+    }
+
+    pub fn equals<T: Into<Bson>>(mut self, field: &str, value: T) -> Self {
+        self.clauses.push(doc! { field: value });
+        self
+    }
+
+    pub fn range<T: Into<Bson>>(mut self, field: &str, min: Option<T>, max: Option<T>) -> Self {
+        if let Some(bounds) = bounds(min, max) {
+            self.clauses.push(doc! { field: bounds });
+        }
+        self
+    }
+
+    pub fn nutrient(self, name: &str, min: Option<f32>, max: Option<f32>) -> Self {
+        self.element_match("nutrition.nutrients", name, min, max)
+    }
+
+    pub fn property(self, name: &str, min: Option<f32>, max: Option<f32>) -> Self {
+        self.element_match("nutrition.properties", name, min, max)
+    }
+
+    fn element_match(mut self, field: &str, name: &str, min: Option<f32>, max: Option<f32>) -> Self {
+        if let Some(bounds) = bounds(min, max) {
+            self.clauses.push(doc! {
+                field: { "$elemMatch": { "name": name, "amount": bounds } }
+            });
+        }
+        self
+    }
+
+    pub fn build(mut self) -> Document {
+        match self.clauses.len() {
+            0 => doc! {},
+            1 => self.clauses.remove(0),
+            _ => doc! { "$and": self.clauses }
+        }
+    }
+}
+
+// `$text` gives meaning to quotes (phrases) and a leading `-` (negation); user input gets neither.
+pub fn escape_text_search(query: &str) -> String {
+    query.split_whitespace()
+        .map(|term| term.replace(['"', '\\'], ""))
+        .map(|term| term.trim_start_matches('-').to_string())
+        .filter(|term| !term.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn is_text_search(filters: &Filters) -> bool {
+    constraints(filters).iter().any(|constraint| matches!(constraint, Constraint::Text(_)))
+}
+
+fn bounds<T: Into<Bson>>(min: Option<T>, max: Option<T>) -> Option<Document> {
+    let mut bounds = doc! {};
+
+    if let Some(min) = min {
+        bounds.insert("$gte", min);
+    }
+
+    if let Some(max) = max {
+        bounds.insert("$lte", max);
+    }
+
+    (!bounds.is_empty()).then_some(bounds)
+}
+
+pub fn build_filter(filters: Filters) -> Document {
+    constraints(&filters).into_iter()
+        .fold(FilterBuilder::new(), |builder, constraint| match constraint {
+            Constraint::Text(terms) => builder.text_search(&terms),
+            Constraint::Prefix(term) => builder.word_prefix(&term),
+            Constraint::AnyTag(list, values) => builder.any_of(tag_field(list), values),
+            Constraint::Range(Count::ReadyInMinutes, min, max) => builder.range("ready_in_minutes", min, max),
+            Constraint::Range(Count::Servings, min, max) => builder.range("servings", min, max),
+            Constraint::Element(ElementList::Nutrients, name, min, max) => builder.nutrient(&name, min, max),
+            Constraint::Element(ElementList::Properties, name, min, max) => builder.property(&name, min, max),
+            Constraint::Flag(Flag::DairyFree) => builder.equals("dairy_free", true),
+            Constraint::Flag(Flag::GlutenFree) => builder.equals("gluten_free", true),
+            Constraint::NoAllergens(intolerances) => builder.none_of("allergens", intolerances.iter().map(|intolerance| intolerance.name().to_string()).collect())
+        })
+        .build()
+}
+
+fn tag_field(list: TagList) -> &'static str {
+    match list {
+        TagList::Diets => "diets",
+        TagList::Cuisines => "cuisines",
+        TagList::DishTypes => "dish_types"
+    }
+}
+
+pub fn validate_filters(filters: &Filters) -> Result<(), AppError> {
+    let mut errors = Map::new();
+
+    let mut check = |field: String, min: Option<f64>, max: Option<f64>| {
+        // `NaN` parses as a float and slips past every comparison below.
+        if min.is_some_and(|min| !min.is_finite()) || max.is_some_and(|max| !max.is_finite()) {
+            errors.insert(field, json!("must be a finite number"));
+        } else if min.is_some_and(|min| min < 0.0) || max.is_some_and(|max| max < 0.0) {
+            errors.insert(field, json!("must not be negative"));
+        } else if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                errors.insert(field, json!(format!("minimum {} is greater than maximum {}", min, max)));
+            }
+        }
+    };
+
+    check("ready_time".to_string(), filters.min_ready_time.map(|min| min as f64), filters.max_ready_time.map(|max| max as f64));
+    check("servings".to_string(), filters.min_servings.map(|min| min as f64), filters.max_servings.map(|max| max as f64));
+    check("max_calories".to_string(), None, filters.max_calories.map(f64::from));
+    check("max_fats".to_string(), None, filters.max_fats.map(f64::from));
+    check("max_carbs".to_string(), None, filters.max_carbs.map(f64::from));
+    check("max_glycemic_index".to_string(), None, filters.max_glycemic_index.map(f64::from));
+
+    for range in &filters.nutrients {
+        check(format!("nutrients.{}", range.name), range.min.map(f64::from), range.max.map(f64::from));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Validation {
+            message: "Invalid filter ranges".to_string(),
+            details: Some(Value::Object(errors))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::filters;
+
+    fn nutrient(name: &str, bounds: Document) -> Document {
+        doc! { "nutrition.nutrients": { "$elemMatch": { "name": name, "amount": bounds } } }
+    }
+
+    #[test]
+    fn nutrient_limits_are_separate_clauses_under_and() {
+        let filter = build_filter(filters(json!({ "max_calories": 500, "max_fats": 20, "max_carbs": 60 })));
+
+        assert_eq!(filter, doc! {
+            "$and": [
+                nutrient("Calories", doc! { "$lte": 500.0 }),
+                nutrient("Fat", doc! { "$lte": 20.0 }),
+                nutrient("Carbohydrates", doc! { "$lte": 60.0 })
+            ]
+        });
+    }
+
+    #[test]
+    fn single_clause_is_not_wrapped() {
+        assert_eq!(build_filter(filters(json!({ "max_calories": 500 }))), nutrient("Calories", doc! { "$lte": 500.0 }));
+        assert_eq!(build_filter(filters(json!({}))), doc! {});
+    }
+
+    #[test]
+    fn nutrient_range_sets_both_bounds() {
+        let filter = build_filter(filters(json!({ "nutrients": [
+            { "name": "Protein", "min": 10, "max": 30 },
+            { "name": "Sugar", "max": 5 }
+        ] })));
+
+        assert_eq!(filter, doc! {
+            "$and": [
+                nutrient("Protein", doc! { "$gte": 10.0, "$lte": 30.0 }),
+                nutrient("Sugar", doc! { "$lte": 5.0 })
+            ]
+        });
+    }
+
+    #[test]
+    fn intolerances_check_flags_and_allergens() {
+        let filter = build_filter(filters(json!({ "intolerances": "dairy,gluten,peanut" })));
+
+        assert_eq!(filter, doc! {
+            "$and": [
+                { "dairy_free": true },
+                { "gluten_free": true },
+                { "allergens": { "$nin": ["dairy", "gluten", "peanut"] } }
+            ]
+        });
+    }
+
+    #[test]
+    fn constraints_leave_out_empty_filters() {
+        let constraints = constraints(&filters(json!({
+            "query": "  ",
+            "diets": "vegan",
+            "cuisines": [],
+            "max_servings": 4,
+            "healthy": true,
+            "nutrients": [{ "name": "Sugar" }],
+            "intolerances": "gluten"
+        })));
+
+        assert_eq!(constraints, [
+            Constraint::AnyTag(TagList::Diets, vec!["vegan".to_string()]),
+            Constraint::Range(Count::Servings, None, Some(4)),
+            Constraint::Element(ElementList::Properties, "Nutrition Score".to_string(), Some(HEALTHY_NUTRITION_SCORE), None),
+            Constraint::Flag(Flag::GlutenFree),
+            Constraint::NoAllergens(vec![Intolerance::Gluten])
+        ]);
+    }
+
+    #[test]
+    fn non_finite_limits_are_rejected() {
+        let mut nan_calories = filters(json!({}));
+        nan_calories.max_calories = Some(f32::NAN);
+        assert!(validate_filters(&nan_calories).is_err());
+
+        let mut infinite_range = filters(json!({ "nutrients": [{ "name": "Protein" }] }));
+        infinite_range.nutrients[0].min = Some(f32::INFINITY);
+        assert!(validate_filters(&infinite_range).is_err());
+
+        assert!(validate_filters(&filters(json!({ "max_calories": 500 }))).is_ok());
+    }
+}
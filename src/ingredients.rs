@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+use serde::Serialize;
+
+use crate::models::Recipe;
+
+// Spellings that should count as the same ingredient, mapped to the form we compare on.
+const SYNONYMS: [(&str, &str); 12] = [
+    ("scallion", "green onion"),
+    ("spring onion", "green onion"),
+    ("cilantro", "coriander"),
+    ("garbanzo bean", "chickpea"),
+    ("garbanzo", "chickpea"),
+    ("aubergine", "eggplant"),
+    ("courgette", "zucchini"),
+    ("capsicum", "bell pepper"),
+    ("prawn", "shrimp"),
+    ("confectioners sugar", "powdered sugar"),
+    ("icing sugar", "powdered sugar"),
+    ("minced beef", "ground beef")
+];
+
+// Assumed to be in every kitchen, so never reported as missing.
+const STAPLES: [&str; 3] = ["water", "salt", "ice"];
+
+fn singular(word: &str) -> String {
+    if word.len() <= 3 || word.ends_with("ss") || word.ends_with("us") {
+        word.to_string()
+    } else if let Some(stem) = word.strip_suffix("ies") {
+        format!("{}y", stem)
+    } else if let Some(stem) = word.strip_suffix("oes") {
+        format!("{}o", stem)
+    } else if ["ches", "shes", "xes", "sses"].iter().any(|suffix| word.ends_with(suffix)) {
+        word[..word.len() - 2].to_string()
+    } else if let Some(stem) = word.strip_suffix('s') {
+        stem.to_string()
+    } else {
+        word.to_string()
+    }
+}
+
+// Lowercases, drops punctuation, singularizes each word and applies synonyms.
+pub fn normalize(name: &str) -> String {
+    let cleaned: String = name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c.is_whitespace() { c } else { ' ' })
+        .collect();
+
+    let mut normalized = format!(" {} ", cleaned.split_whitespace()
+        .map(singular)
+        .collect::<Vec<_>>()
+        .join(" "));
+
+    for (alias, canonical) in SYNONYMS {
+        normalized = normalized.replace(&format!(" {} ", alias), &format!(" {} ", canonical));
+    }
+
+    normalized.trim().to_string()
+}
+
+// Words worth looking up in the database for an ingredient the user has: those of the
+// normalized name and of every alias of it, so "green onion" also finds "scallions".
+pub fn search_terms(normalized: &str) -> Vec<String> {
+    let spellings = std::iter::once(normalized)
+        .chain(SYNONYMS.iter().filter(|(_, canonical)| *canonical == normalized).map(|(alias, _)| *alias));
+
+    let mut terms: Vec<String> = spellings
+        .flat_map(|spelling| spelling.split_whitespace())
+        .filter(|word| word.len() > 2)
+        .map(|word| word.to_string())
+        .collect();
+
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+// "onion" is covered by "red onion", but "red onion" is not covered by "onion".
+fn covers(available: &str, required: &str) -> bool {
+    let available: HashSet<&str> = available.split_whitespace().collect();
+    required.split_whitespace().all(|word| available.contains(word))
+}
+
+#[derive(Debug, Serialize)]
+pub struct PantryMatch {
+    pub recipe: Recipe,
+    pub used_ingredients: Vec<String>,
+    pub missing_ingredients: Vec<String>,
+    pub coverage: f32
+}
+
+pub fn match_recipe(recipe: Recipe, have: &[String], exclude: &[String]) -> Option<PantryMatch> {
+    let mut used_ingredients = Vec::new();
+    let mut missing_ingredients = Vec::new();
+
+    for ingredient in &recipe.ingredients {
+        let required = normalize(&ingredient.name);
+
+        // Excluding "peanut" rules out "peanut butter" too.
+        if exclude.iter().any(|excluded| covers(&required, excluded)) {
+            return None;
+        }
+
+        if STAPLES.contains(&required.as_str()) {
+            continue;
+        }
+
+        if have.iter().any(|available| covers(available, &required)) {
+            used_ingredients.push(ingredient.name.clone());
+        } else {
+            missing_ingredients.push(ingredient.name.clone());
+        }
+    }
+
+    if used_ingredients.is_empty() {
+        return None;
+    }
+
+    let coverage = used_ingredients.len() as f32 / (used_ingredients.len() + missing_ingredients.len()) as f32;
+
+    Some(PantryMatch { recipe, used_ingredients, missing_ingredients, coverage })
+}
+
+// Fewest missing ingredients first, then the best covered, then the ones using most of what's on hand.
+pub fn rank(mut matches: Vec<PantryMatch>) -> Vec<PantryMatch> {
+    matches.sort_by(|a, b| a.missing_ingredients.len().cmp(&b.missing_ingredients.len())
+        .then(b.coverage.total_cmp(&a.coverage))
+        .then(b.used_ingredients.len().cmp(&a.used_ingredients.len())));
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn recipe(ingredients: &[&str]) -> Recipe {
+        serde_json::from_value(json!({
+            "id": "1",
+            "title": "Test recipe",
+            "summary": "",
+            "image": "https://example.com/recipe.jpg",
+            "vegetarian": true,
+            "vegan": true,
+            "gluten_free": true,
+            "dairy_free": true,
+            "ready_in_minutes": 10,
+            "servings": 2,
+            "ingredients": ingredients.iter().map(|name| json!({ "name": name, "amount": 1.0, "unit": "" })).collect::<Vec<_>>(),
+            "nutrition": { "nutrients": [], "properties": [] },
+            "cuisines": [],
+            "dish_types": [],
+            "diets": [],
+            "instructions": []
+        })).unwrap()
+    }
+
+    fn names(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| normalize(value)).collect()
+    }
+
+    #[test]
+    fn specific_item_covers_generic_requirement() {
+        assert!(covers(&normalize("russet potatoes"), &normalize("potatoes")));
+        assert!(covers(&normalize("red onion"), &normalize("onion")));
+    }
+
+    #[test]
+    fn generic_item_does_not_cover_specific_requirement() {
+        assert!(!covers(&normalize("oil"), &normalize("olive oil")));
+        assert!(!covers(&normalize("butter"), &normalize("peanut butter")));
+        assert!(!covers(&normalize("milk"), &normalize("coconut milk")));
+    }
+
+    #[test]
+    fn match_uses_and_misses_in_the_right_direction() {
+        let matched = match_recipe(recipe(&["potatoes", "olive oil", "salt"]), &names(&["russet potatoes", "oil"]), &[]).unwrap();
+
+        assert_eq!(matched.used_ingredients, vec!["potatoes"]);
+        assert_eq!(matched.missing_ingredients, vec!["olive oil"]);
+    }
+
+    #[test]
+    fn exclude_rules_out_specific_ingredients_only() {
+        let have = names(&["bread"]);
+
+        assert!(match_recipe(recipe(&["bread", "peanut butter"]), &have, &names(&["peanut"])).is_none());
+        assert!(match_recipe(recipe(&["bread", "butter"]), &have, &names(&["peanut butter"])).is_some());
+    }
+}
@@ -1,7 +1,6 @@
-use tokio::time::{ sleep, Duration };
-use std::env;
+use tokio::{sync::OnceCell, time::{ sleep, Duration }};
+use std::{env, sync::Arc};
 use dotenv::dotenv;
-use tokio;
 use actix_web::{
     web,
     App,
@@ -11,11 +10,38 @@ use actix_web::{
 };
 use actix_cors::Cors;
 
+use auth::{ApiKeyAuth, ApiKeyStore, InMemoryApiKeyStore, MongoApiKeyStore, Scope, SqliteApiKeyStore};
+use errors::RequestId;
+use memory::InMemoryRecipeRepository;
+use models::Filters;
+use pagination::CursorCodec;
+use rate_limit::{InMemoryRateLimitStore, RateLimitStore, RateLimiter, RateLimits};
+use repository::{MongoRecipeRepository, RecipeRepository};
+use sqlite::SqliteRecipeRepository;
+
+mod allergens;
+mod auth;
+mod bulk;
 mod fetch_data;
+mod filter_builder;
+mod ingredients;
 mod db;
+mod errors;
+mod etag;
+mod memory;
+mod migrations;
 mod api;
 mod api_structs;
 mod models;
+mod pagination;
+mod patch;
+mod rate_limit;
+mod repository;
+mod sort;
+mod sqlite;
+#[cfg(test)]
+mod test_support;
+mod validation;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
@@ -25,38 +51,135 @@ async fn main() -> std::io::Result<()> {
     std::env::set_var("RUST_LOG", "debug");
     std::env::set_var("RUST_BACKTRACE", "1");
 
-    let access_token = env::var("API_KEY").expect("API_KEY not set");
-
-    let database = db::connect().await;
-    let database2 = database.clone();
-    
     env_logger::init();
+
+    // `feastly-api migrate [--dry-run]` brings the database up to date, or reports what that would change, and exits.
+    if env::args().nth(1).as_deref() == Some("migrate") {
+        let dry_run = env::args().any(|arg| arg == "--dry-run");
+        let outcomes = migrations::run_pending(&db::connect().await, dry_run).await.expect("Failed to run migrations");
+
+        if outcomes.is_empty() {
+            log::info!("No pending migrations");
+        }
+        for outcome in outcomes {
+            log::info!("{} ({}): {} recipes {}", outcome.id, outcome.description, outcome.documents, if dry_run { "would change" } else { "changed" });
+        }
+        return Ok(());
+    }
+
+    // Demo mode needs neither MongoDB nor Spoonacular: recipes come from a fixture and, like API keys, live in memory.
+    let demo = env::args().any(|arg| arg == "--demo");
+
+    let access_token = if demo { String::new() } else { env::var("API_KEY").expect("API_KEY not set") };
+
+    // MongoDB and SQLite are only connected to if one of the stores below lives there.
+    let mongo = OnceCell::new();
+    let sqlite = OnceCell::new();
+    let sqlite_pool = || async {
+        let url = env::var("SQLITE_DATABASE_URL").unwrap_or_else(|_| sqlite::DEFAULT_URL.to_string());
+        sqlite::connect(&url).await.expect("Failed to open SQLite database")
+    };
+
+    let recipe_store = if demo { "memory".to_string() } else { env::var("RECIPE_STORE").unwrap_or_default() };
+
+    let recipes: Arc<dyn RecipeRepository> = match recipe_store.as_str() {
+        "memory" => {
+            let fixture = env::var("RECIPE_FIXTURE").ok().or_else(|| demo.then(|| memory::DEFAULT_FIXTURE.to_string()));
+            Arc::new(match fixture {
+                Some(path) => InMemoryRecipeRepository::from_fixture(&path).expect("Failed to load recipe fixture"),
+                None => InMemoryRecipeRepository::default()
+            })
+        },
+        "sqlite" => Arc::new(SqliteRecipeRepository::new(sqlite.get_or_init(sqlite_pool).await.clone())),
+        _ => {
+            let database = mongo.get_or_init(db::connect).await;
+
+            // Migrations go first, as the unique `id` index can only be built once ids are all strings.
+            for outcome in migrations::run_pending(database, false).await.expect("Failed to run migrations") {
+                log::info!("Applied migration {}, {} recipes changed", outcome.id, outcome.documents);
+            }
+
+            let indexes = db::ensure_indexes(&database.collection("Recipes")).await.expect("Failed to list indexes");
+            log::info!("Indexes created: [{}], already present: [{}]", indexes.created.join(", "), indexes.existing.join(", "));
+            for (name, e) in indexes.failed {
+                // The import only stays off user-created recipes thanks to this index, so don't serve without it.
+                if name == db::UNIQUE_ID_INDEX {
+                    panic!("Failed to create index {}: {}", name, e);
+                }
+                log::error!("Failed to create index {}: {}", name, e);
+            }
+
+            Arc::new(MongoRecipeRepository::new(database.collection("Recipes")))
+        }
+    };
+    let importer = recipes.clone();
+
+    // Without `API_KEY_STORE`, keys go in SQLite when recipes do and in MongoDB otherwise.
+    let key_store: Arc<dyn ApiKeyStore> = match env::var("API_KEY_STORE").as_deref() {
+        _ if demo => Arc::new(InMemoryApiKeyStore::default()),
+        Ok("memory") => Arc::new(InMemoryApiKeyStore::default()),
+        Ok("sqlite") => Arc::new(SqliteApiKeyStore::new(sqlite.get_or_init(sqlite_pool).await.clone())),
+        Err(_) if recipe_store == "sqlite" => Arc::new(SqliteApiKeyStore::new(sqlite.get_or_init(sqlite_pool).await.clone())),
+        _ => {
+            let store = MongoApiKeyStore::new(mongo.get_or_init(db::connect).await.collection("ApiKeys"));
+            store.ensure_indexes().await.expect("Failed to create API key indexes");
+            Arc::new(store)
+        }
+    };
+
+    if let Ok(bootstrap_key) = env::var("BOOTSTRAP_API_KEY") {
+        auth::bootstrap(key_store.as_ref(), bootstrap_key.trim()).await.expect("Failed to bootstrap API key");
+    }
+
+    let rate_limit_store: Arc<dyn RateLimitStore> = Arc::new(InMemoryRateLimitStore::default());
+    let rate_limits = RateLimits::from_env();
+
+    if demo && env::var("BOOTSTRAP_API_KEY").is_err() {
+        let (raw_key, _) = key_store.create("demo", vec![Scope::Admin], None).await.expect("Failed to create demo API key");
+        log::info!("Demo mode, authenticate with API key {}", raw_key);
+    }
+
+    match recipes.count(Filters::default()).await {
+        Ok(count) => log::info!("Serving {} recipes", count),
+        Err(e) => log::warn!("Could not count recipes: {}", e)
+    }
+
+    let cursor_codec = web::Data::new(CursorCodec::from_env());
     
     let server = HttpServer::new(move || {
         let logger = Logger::default();
         App::new()
+            .wrap(RateLimiter::new(rate_limit_store.clone(), rate_limits))
+            .wrap(ApiKeyAuth::new(key_store.clone()))
             .wrap(Cors::default()
                 .allow_any_origin()
-                .allowed_methods(vec!["GET"])
-                .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
+                .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+                .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT, http::header::CONTENT_TYPE, http::header::IF_MATCH, http::header::IF_NONE_MATCH])
+                .expose_headers(vec![http::header::ETAG, http::header::LOCATION])
                 .supports_credentials()
             )
+            .wrap(RequestId)
             .wrap(logger)
-            .app_data(web::Data::new(database.clone()))
-            .service(web::resource("/recipes").to(api::get_data))
-            .service(web::resource("/recipe").to(api::get_single_data))
-            .service(web::resource("/create").to(api::create_data))
-            .service(web::resource("/update").to(api::update_data))
-            .service(web::resource("/delete").to(api::delete_data))
+            .app_data(web::Data::new(recipes.clone()))
+            .app_data(web::Data::new(key_store.clone()))
+            .app_data(cursor_codec.clone())
+            .configure(api::configure)
     })
     .bind(("0.0.0.0", 8000))?
     .run();
 
+    // Demo data is never refreshed from Spoonacular.
+    if demo {
+        return server.await;
+    }
+
     tokio::spawn(server);
 
     loop {
         sleep(Duration::from_secs(43200)).await;
-        fetch_data::fetch_and_store_recipes(&access_token, &database2).await;
+        if let Err(e) = fetch_data::fetch_and_store_recipes(&access_token, importer.as_ref()).await {
+            log::error!("Failed to refresh recipes: {}", e);
+        }
     }
 
 }
\ No newline at end of file
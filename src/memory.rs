@@ -0,0 +1,366 @@
+use std::{cmp::Ordering, collections::HashMap, fs, io, path::Path, sync::RwLock};
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+use regex::Regex;
+
+use crate::{
+    allergens,
+    filter_builder::{self, Constraint, Count, ElementList, Flag, TagList},
+    models::{Filters, Recipe, RecipeSource},
+    pagination::{Cursor, Direction},
+    patch::PatchedRecipe,
+    repository::{KeysetPage, Page, RecipeError, RecipeRepository},
+    sort::{SortField, SortKey}
+};
+
+pub const DEFAULT_FIXTURE: &str = "fixtures/recipes.json";
+
+// Keeps everything in a `Vec`, which is plenty for tests and the demo data set.
+#[derive(Default)]
+pub struct InMemoryRecipeRepository {
+    recipes: RwLock<Vec<Recipe>>
+}
+
+impl InMemoryRecipeRepository {
+    // Seeds the store from a JSON array of recipes, stored the way the import stores them.
+    pub fn from_fixture(path: impl AsRef<Path>) -> io::Result<Self> {
+        let recipes: Vec<Recipe> = serde_json::from_slice(&fs::read(path)?)?;
+        let repository = InMemoryRecipeRepository::default();
+
+        for recipe in &recipes {
+            repository.put(recipe).map_err(io::Error::other)?;
+        }
+
+        Ok(repository)
+    }
+
+    fn put(&self, recipe: &Recipe) -> Result<(), RecipeError> {
+        let mut recipes = self.recipes.write().unwrap();
+
+        match recipes.iter_mut().find(|stored| stored.id == recipe.id) {
+            Some(stored) if stored.source == RecipeSource::User => return Err(RecipeError::AlreadyExists(recipe.id.clone())),
+            Some(stored) => *stored = stored_recipe(recipe, stored._id, stored.version + 1, recipe.source),
+            None => recipes.push(stored_recipe(recipe, Some(ObjectId::new()), 1, recipe.source))
+        }
+
+        Ok(())
+    }
+}
+
+fn stored_recipe(recipe: &Recipe, _id: Option<ObjectId>, version: i64, source: RecipeSource) -> Recipe {
+    Recipe { _id, allergens: allergens::detect(recipe), version, source, ..recipe.clone() }
+}
+
+fn position(recipes: &[Recipe], id: &str, versions: Option<&[i64]>) -> Result<usize, RecipeError> {
+    let index = recipes.iter().position(|recipe| recipe.id == id).ok_or_else(|| RecipeError::NotFound(id.to_string()))?;
+
+    match versions {
+        Some(versions) if !versions.contains(&recipes[index].version) => Err(RecipeError::VersionMismatch(id.to_string())),
+        _ => Ok(index)
+    }
+}
+
+// A rough stand-in for the English stemming of a MongoDB text index, enough for plurals to find singulars.
+fn stem(word: &str) -> String {
+    let word = word.to_lowercase();
+
+    for (suffix, replacement) in [("ies", "y"), ("oes", "o"), ("ches", "ch"), ("shes", "sh"), ("sses", "ss"), ("xes", "x")] {
+        if let Some(stem) = word.strip_suffix(suffix) {
+            return format!("{}{}", stem, replacement);
+        }
+    }
+
+    match word.strip_suffix('s') {
+        Some(stem) if !stem.ends_with('s') && !stem.is_empty() => stem.to_string(),
+        _ => word
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).map(stem)
+}
+
+fn word_prefix(term: &str) -> Regex {
+    Regex::new(&format!(r"(?i)\b{}", regex::escape(term))).expect("An escaped term is a valid pattern")
+}
+
+fn within<T: PartialOrd>(value: T, min: Option<T>, max: Option<T>) -> bool {
+    min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+}
+
+// `filter_builder::constraints`, checked against each recipe.
+struct Matcher {
+    constraints: Vec<Constraint>,
+    terms: Vec<String>,
+    prefix: Option<Regex>
+}
+
+impl Matcher {
+    fn new(filters: &Filters) -> Self {
+        let constraints = filter_builder::constraints(filters);
+        let mut terms = Vec::new();
+        let mut prefix = None;
+
+        for constraint in &constraints {
+            match constraint {
+                Constraint::Text(query) => terms = words(query).collect(),
+                Constraint::Prefix(query) => prefix = Some(word_prefix(query)),
+                _ => {}
+            }
+        }
+
+        Matcher { constraints, terms, prefix }
+    }
+
+    fn is_text_search(&self) -> bool {
+        !self.terms.is_empty()
+    }
+
+    // Weighted like the MongoDB text index.
+    fn score(&self, recipe: &Recipe) -> usize {
+        let hits = |text: &str| words(text).filter(|word| self.terms.contains(word)).count();
+
+        10 * hits(&recipe.title)
+            + 5 * recipe.ingredients.iter().map(|ingredient| hits(&ingredient.name)).sum::<usize>()
+            + 2 * hits(&recipe.summary)
+            + recipe.instructions.iter().map(|step| hits(&step.step)).sum::<usize>()
+    }
+
+    fn matches(&self, recipe: &Recipe) -> bool {
+        self.constraints.iter().all(|constraint| match constraint {
+            Constraint::Text(_) => self.score(recipe) > 0,
+            Constraint::Prefix(_) => self.prefix.as_ref().is_some_and(|prefix| {
+                prefix.is_match(&recipe.title) || recipe.ingredients.iter().any(|ingredient| prefix.is_match(&ingredient.name))
+            }),
+            Constraint::AnyTag(list, wanted) => {
+                let values = match list {
+                    TagList::Diets => &recipe.diets,
+                    TagList::Cuisines => &recipe.cuisines,
+                    TagList::DishTypes => &recipe.dish_types
+                };
+                values.iter().any(|value| wanted.contains(value))
+            },
+            Constraint::Range(Count::ReadyInMinutes, min, max) => within(recipe.ready_in_minutes, *min, *max),
+            Constraint::Range(Count::Servings, min, max) => within(recipe.servings, *min, *max),
+            Constraint::Element(ElementList::Nutrients, name, min, max) => recipe.nutrition.nutrients.iter()
+                .any(|nutrient| nutrient.name == *name && within(nutrient.amount, *min, *max)),
+            Constraint::Element(ElementList::Properties, name, min, max) => recipe.nutrition.properties.iter()
+                .any(|property| property.name == *name && within(property.amount, *min, *max)),
+            Constraint::Flag(Flag::DairyFree) => recipe.dairy_free,
+            Constraint::Flag(Flag::GlutenFree) => recipe.gluten_free,
+            Constraint::NoAllergens(intolerances) => !recipe.allergens.iter().any(|allergen| intolerances.contains(allergen))
+        })
+    }
+}
+
+fn compare(a: &Recipe, b: &Recipe, field: SortField) -> Ordering {
+    match field {
+        SortField::ReadyTime => a.ready_in_minutes.cmp(&b.ready_in_minutes),
+        SortField::Servings => a.servings.cmp(&b.servings),
+        SortField::Calories => a.calories().partial_cmp(&b.calories()).unwrap_or(Ordering::Equal),
+        SortField::HealthScore => a.health_score().partial_cmp(&b.health_score()).unwrap_or(Ordering::Equal),
+        SortField::Title => a.title.cmp(&b.title),
+        SortField::Recency => a._id.cmp(&b._id)
+    }
+}
+
+#[async_trait]
+impl RecipeRepository for InMemoryRecipeRepository {
+    async fn create(&self, recipe: &Recipe) -> Result<Recipe, RecipeError> {
+        let mut recipes = self.recipes.write().unwrap();
+
+        if recipes.iter().any(|stored| stored.id == recipe.id) {
+            return Err(RecipeError::AlreadyExists(recipe.id.clone()));
+        }
+
+        let stored = stored_recipe(recipe, Some(ObjectId::new()), 1, recipe.source);
+        recipes.push(stored.clone());
+        Ok(stored)
+    }
+
+    async fn upsert(&self, recipe: &Recipe) -> Result<(), RecipeError> {
+        self.put(recipe)
+    }
+
+    async fn read(&self, id: &str) -> Result<Option<Recipe>, RecipeError> {
+        Ok(self.recipes.read().unwrap().iter().find(|recipe| recipe.id == id).cloned())
+    }
+
+    async fn update(&self, id: &str, recipe: &Recipe, versions: Option<&[i64]>) -> Result<Recipe, RecipeError> {
+        let mut recipes = self.recipes.write().unwrap();
+        let index = position(&recipes, id, versions)?;
+
+        let current = &recipes[index];
+        recipes[index] = stored_recipe(recipe, current._id, current.version + 1, current.source);
+        Ok(recipes[index].clone())
+    }
+
+    async fn patch(&self, id: &str, patched: &PatchedRecipe, version: i64) -> Result<Recipe, RecipeError> {
+        self.update(id, &patched.recipe, Some(&[version])).await
+    }
+
+    async fn delete(&self, id: &str, versions: Option<&[i64]>) -> Result<(), RecipeError> {
+        let mut recipes = self.recipes.write().unwrap();
+        let index = position(&recipes, id, versions)?;
+
+        recipes.remove(index);
+        Ok(())
+    }
+
+    async fn filter(&self, filters: Filters, sort: &[SortKey], page: usize, page_size: usize) -> Result<Page, RecipeError> {
+        let recipes = self.recipes.read().unwrap();
+        let matcher = Matcher::new(&filters);
+
+        let mut matched: Vec<(usize, &Recipe)> = recipes.iter()
+            .filter(|recipe| matcher.matches(recipe))
+            .map(|recipe| (matcher.score(recipe), recipe))
+            .collect();
+
+        let by_relevance = sort.is_empty() && matcher.is_text_search();
+
+        matched.sort_by(|(a_score, a), (b_score, b)| {
+            let relevance = if by_relevance { b_score.cmp(a_score) } else { Ordering::Equal };
+
+            sort.iter()
+                .fold(relevance, |ordering, key| ordering.then_with(|| {
+                    let ordering = compare(a, b, key.field);
+                    if key.descending { ordering.reverse() } else { ordering }
+                }))
+                .then_with(|| a._id.cmp(&b._id))
+        });
+
+        Ok(Page {
+            total_items: matched.len() as u64,
+            recipes: matched.into_iter().skip((page - 1) * page_size).take(page_size).map(|(_, recipe)| recipe.clone()).collect()
+        })
+    }
+
+    async fn filter_keyset(&self, filters: Filters, cursor: Option<&Cursor>, page_size: usize) -> Result<KeysetPage, RecipeError> {
+        let recipes = self.recipes.read().unwrap();
+        let matcher = Matcher::new(&filters);
+        let direction = cursor.map_or(Direction::Next, |cursor| cursor.direction);
+
+        let mut matched: Vec<&Recipe> = recipes.iter()
+            .filter(|recipe| matcher.matches(recipe))
+            .filter(|recipe| cursor.is_none_or(|cursor| match cursor.direction {
+                Direction::Next => recipe._id > Some(cursor.id),
+                Direction::Prev => recipe._id < Some(cursor.id)
+            }))
+            .collect();
+
+        matched.sort_by_key(|recipe| recipe._id);
+        if direction == Direction::Prev {
+            matched.reverse();
+        }
+
+        let has_more = matched.len() > page_size;
+        matched.truncate(page_size);
+
+        if direction == Direction::Prev {
+            matched.reverse();
+        }
+
+        Ok(KeysetPage { recipes: matched.into_iter().cloned().collect(), has_more })
+    }
+
+    async fn count(&self, filters: Filters) -> Result<u64, RecipeError> {
+        let matcher = Matcher::new(&filters);
+        Ok(self.recipes.read().unwrap().iter().filter(|recipe| matcher.matches(recipe)).count() as u64)
+    }
+
+    async fn find_by_ingredient_terms(&self, terms: &[String], limit: usize) -> Result<Vec<Recipe>, RecipeError> {
+        let patterns: Vec<Regex> = terms.iter().map(|term| word_prefix(term)).collect();
+
+        let recipes = self.recipes.read().unwrap();
+
+        let mut candidates: Vec<(usize, usize, &Recipe)> = recipes.iter()
+            .map(|recipe| {
+                let matched = recipe.ingredients.iter()
+                    .filter(|ingredient| patterns.iter().any(|pattern| pattern.is_match(&ingredient.name)))
+                    .count();
+                (recipe.ingredients.len() - matched, matched, recipe)
+            })
+            .filter(|(_, matched, _)| *matched > 0)
+            .collect();
+
+        // Fewest unmatched ingredients first, as `db::find_by_ingredient_terms` orders them.
+        candidates.sort_by(|(a_unmatched, a_matched, a), (b_unmatched, b_matched, b)| a_unmatched.cmp(b_unmatched)
+            .then(b_matched.cmp(a_matched))
+            .then(a._id.cmp(&b._id)));
+
+        Ok(candidates.into_iter().take(limit).map(|(_, _, recipe)| recipe.clone()).collect())
+    }
+
+    async fn find_duplicates(&self, fingerprints: &[String]) -> Result<HashMap<String, String>, RecipeError> {
+        Ok(self.recipes.read().unwrap().iter()
+            .map(|recipe| (recipe.fingerprint(), recipe.id.clone()))
+            .filter(|(fingerprint, _)| fingerprints.contains(fingerprint))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{sort::parse_sort, test_support::{filter_cases, filters, repository}};
+
+    fn ids(recipes: &[Recipe]) -> Vec<&str> {
+        recipes.iter().map(|recipe| recipe.id.as_str()).collect()
+    }
+
+    async fn filtered(filters: Filters, sort: &str) -> Vec<String> {
+        let sort = if sort.is_empty() { Vec::new() } else { parse_sort(sort).unwrap() };
+        let page = repository().filter(filters, &sort, 1, 10).await.unwrap();
+        ids(&page.recipes).into_iter().map(str::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn filters_match_the_fixture() {
+        for (value, expected) in filter_cases() {
+            assert_eq!(filtered(filters(value.clone()), "").await, expected, "{}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn sorts_and_pages() {
+        assert_eq!(filtered(Filters::default(), "-calories").await, ["716429", "795751", "782601", "715594"]);
+        assert_eq!(filtered(Filters::default(), "calories").await, ["715594", "782601", "795751", "716429"]);
+
+        let page = repository().filter(Filters::default(), &parse_sort("-calories").unwrap(), 2, 3).await.unwrap();
+        assert_eq!(page.total_items, 4);
+        assert_eq!(ids(&page.recipes), ["715594"]);
+    }
+
+    #[tokio::test]
+    async fn text_search_ranks_title_hits_first_unless_sorted() {
+        let by_relevance = filtered(filters(json!({ "query": "garlic basil" })), "").await;
+        assert_eq!(by_relevance[..2], ["715594", "716429"]);
+
+        let by_title = filtered(filters(json!({ "query": "garlic basil" })), "-title").await;
+        assert_eq!(by_title[..2], ["716429", "715594"]);
+    }
+
+    #[tokio::test]
+    async fn keyset_pages_move_both_ways() {
+        let repository = repository();
+
+        let first = repository.filter_keyset(Filters::default(), None, 3).await.unwrap();
+        assert_eq!(ids(&first.recipes), ["716429", "715594", "782601"]);
+        assert!(first.has_more);
+
+        let next = Cursor { id: first.recipes[2]._id.unwrap(), direction: Direction::Next };
+        let second = repository.filter_keyset(Filters::default(), Some(&next), 3).await.unwrap();
+        assert_eq!(ids(&second.recipes), ["795751"]);
+        assert!(!second.has_more);
+
+        let prev = Cursor { id: second.recipes[0]._id.unwrap(), direction: Direction::Prev };
+        let back = repository.filter_keyset(Filters::default(), Some(&prev), 2).await.unwrap();
+        assert_eq!(ids(&back.recipes), ["715594", "782601"]);
+        assert!(back.has_more);
+
+        let vegan = repository.filter_keyset(filters(json!({ "diets": "vegan" })), Some(&next), 3).await.unwrap();
+        assert!(vegan.recipes.is_empty());
+    }
+}
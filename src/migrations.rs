@@ -0,0 +1,148 @@
+use std::collections::HashSet;
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use mongodb::{bson::{self, doc, DateTime, Document}, Collection, Database};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{models::Recipe, patch::COMPUTED};
+
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] mongodb::error::Error),
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] bson::de::Error)
+}
+
+// One document per migration that has run, in `_migrations`.
+#[derive(Debug, Serialize, Deserialize)]
+struct AppliedMigration {
+    id: String,
+    description: String,
+    documents: i64,
+    applied_at: DateTime
+}
+
+pub struct MigrationOutcome {
+    pub id: &'static str,
+    pub description: &'static str,
+    pub documents: u64
+}
+
+// Ids sort in the order migrations run, so new ones go at the end of `MIGRATIONS` with the next number.
+#[async_trait]
+trait Migration: Send + Sync {
+    fn id(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    // Returns how many recipes were changed, or would be in a dry run.
+    async fn run(&self, recipes: &Collection<Document>, dry_run: bool) -> Result<u64, MigrationError>;
+}
+
+struct StringifyIds;
+
+#[async_trait]
+impl Migration for StringifyIds {
+    fn id(&self) -> &'static str {
+        "0001_stringify_recipe_ids"
+    }
+
+    fn description(&self) -> &'static str {
+        "Store numeric Spoonacular ids as strings"
+    }
+
+    // Such documents can't be read back into a `Recipe` at all, so this goes first.
+    async fn run(&self, recipes: &Collection<Document>, dry_run: bool) -> Result<u64, MigrationError> {
+        let filter = doc! { "id": { "$type": "number" } };
+
+        if dry_run {
+            return Ok(recipes.count_documents(filter, None).await?);
+        }
+
+        let result = recipes.update_many(filter, vec![doc! { "$set": { "id": { "$toString": "$id" } } }], None).await?;
+        Ok(result.modified_count)
+    }
+}
+
+struct BackfillDerivedFields;
+
+#[async_trait]
+impl Migration for BackfillDerivedFields {
+    fn id(&self) -> &'static str {
+        "0002_backfill_derived_fields"
+    }
+
+    fn description(&self) -> &'static str {
+        "Add calories, health score, allergens, fingerprint and source to recipes stored without them"
+    }
+
+    // Versions are left alone: a missing one already reads as 0 and keeps matching the ETags clients hold.
+    async fn run(&self, recipes: &Collection<Document>, dry_run: bool) -> Result<u64, MigrationError> {
+        let filter = doc! { "$or": [{ "fingerprint": { "$exists": false } }, { "source": { "$exists": false } }] };
+
+        if dry_run {
+            return Ok(recipes.count_documents(filter, None).await?);
+        }
+
+        let mut cursor = recipes.find(filter, None).await?;
+        let mut changed = 0;
+
+        while let Some(document) = cursor.next().await {
+            let document = document?;
+            let recipe: Recipe = bson::from_document(document.clone())?;
+            let derived = recipe.to_document();
+
+            let mut set = Document::new();
+            for field in COMPUTED {
+                if let Some(value) = derived.get(field) {
+                    set.insert(field, value.clone());
+                }
+            }
+            if !document.contains_key("source") {
+                set.insert("source", recipe.source.name());
+            }
+
+            recipes.update_one(doc! { "_id": document.get("_id").cloned() }, doc! { "$set": set }, None).await?;
+            changed += 1;
+        }
+
+        Ok(changed)
+    }
+}
+
+fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![Box::new(StringifyIds), Box::new(BackfillDerivedFields)]
+}
+
+// Runs every migration not yet recorded in `_migrations`, in order, and stops at the first failure so later
+// migrations never see data an earlier one didn't finish. A dry run records nothing and counts against the
+// current data, so its numbers for later migrations don't account for earlier ones.
+pub async fn run_pending(database: &Database, dry_run: bool) -> Result<Vec<MigrationOutcome>, MigrationError> {
+    let applied_migrations = database.collection::<AppliedMigration>("_migrations");
+    let applied: HashSet<String> = applied_migrations.distinct("id", None, None).await?
+        .into_iter()
+        .filter_map(|id| id.as_str().map(str::to_string))
+        .collect();
+
+    let recipes = database.collection::<Document>("Recipes");
+    let mut outcomes = Vec::new();
+
+    for migration in migrations().iter().filter(|migration| !applied.contains(migration.id())) {
+        log::info!("{} migration {}: {}", if dry_run { "Checking" } else { "Applying" }, migration.id(), migration.description());
+
+        let documents = migration.run(&recipes, dry_run).await?;
+
+        if !dry_run {
+            applied_migrations.insert_one(AppliedMigration {
+                id: migration.id().to_string(),
+                description: migration.description().to_string(),
+                documents: documents as i64,
+                applied_at: DateTime::now()
+            }, None).await?;
+        }
+
+        outcomes.push(MigrationOutcome { id: migration.id(), description: migration.description(), documents });
+    }
+
+    Ok(outcomes)
+}
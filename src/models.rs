@@ -1,10 +1,33 @@
 use mongodb::bson::{oid::ObjectId, Bson, Document};
-use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{allergens::{self, Intolerance}, ingredients::normalize};
+
+// Recipes stored before sources were recorded all came from the Spoonacular import.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecipeSource {
+    #[default]
+    Spoonacular,
+    User
+}
+
+impl RecipeSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecipeSource::Spoonacular => "spoonacular",
+            RecipeSource::User => "user"
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Recipe {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub _id: Option<ObjectId>,
+    #[serde(default)]
     pub id: String,
     pub title: String,
     pub summary: String,
@@ -20,18 +43,73 @@ pub struct Recipe {
     pub cuisines: Vec<String>,
     pub dish_types: Vec<String>,
     pub diets: Vec<String>,
-    pub instructions: Vec<Step>
+    pub instructions: Vec<Step>,
+    #[serde(default)]
+    pub allergens: Vec<Intolerance>,
+    // Bumped by every write; recipes stored before versioning read back as 0.
+    #[serde(default)]
+    pub version: i64,
+    #[serde(default)]
+    pub source: RecipeSource
 }
 
 impl Recipe {
+    // Calories and health score are denormalized onto the document so they can be sorted on through an index,
+    // and allergens and the fingerprint are always recomputed rather than trusted from the payload. The version
+    // and source are left out because only the server sets them, on insert or through `$inc`.
     pub fn to_document(&self) -> Document {
         let bson = mongodb::bson::to_bson(self).expect("Failed to convert to BSON");
-        if let Bson::Document(document) = bson {
+        if let Bson::Document(mut document) = bson {
+            document.insert("calories", self.calories().map_or(Bson::Null, |amount| Bson::Double(amount as f64)));
+            document.insert("health_score", self.health_score().map_or(Bson::Null, |amount| Bson::Double(amount as f64)));
+            document.insert("allergens", allergens::detect(self).iter().map(|intolerance| intolerance.name()).collect::<Vec<_>>());
+            document.insert("fingerprint", self.fingerprint());
+            document.remove("version");
+            document.remove("source");
             document
         } else {
             panic!("Expected a BSON document")
         }
     }
+
+    pub fn nutrient(&self, name: &str) -> Option<f32> {
+        self.nutrition.nutrients.iter()
+            .find(|nutrient| nutrient.name.eq_ignore_ascii_case(name))
+            .map(|nutrient| nutrient.amount)
+    }
+
+    pub fn property(&self, name: &str) -> Option<f32> {
+        self.nutrition.properties.iter()
+            .find(|property| property.name.eq_ignore_ascii_case(name))
+            .map(|property| property.amount)
+    }
+
+    pub fn calories(&self) -> Option<f32> {
+        self.nutrient("Calories")
+    }
+
+    pub fn health_score(&self) -> Option<f32> {
+        self.property("Nutrition Score")
+    }
+
+    // Recipes created through the API belong to the user, under a generated id unless the client brought its own.
+    pub fn into_user_recipe(mut self) -> Recipe {
+        if self.id.trim().is_empty() {
+            self.id = ObjectId::new().to_hex();
+        }
+        self.source = RecipeSource::User;
+        self
+    }
+
+    // Two recipes with the same normalized title and set of ingredients are probably the same recipe,
+    // whatever their amounts, order or spelling of plurals.
+    pub fn fingerprint(&self) -> String {
+        let mut ingredients: Vec<String> = self.ingredients.iter().map(|ingredient| normalize(&ingredient.name)).collect();
+        ingredients.sort();
+        ingredients.dedup();
+
+        hex::encode(Sha256::digest(format!("{}|{}", normalize(&self.title), ingredients.join(",")).as_bytes()))
+    }
     // pub fn from_document(doc: Document) -> Recipe {
     //     mongodb::bson::from_bson(Bson::Document(doc)).expect("Failed to convert from BSON")
     // }
@@ -69,17 +147,118 @@ pub struct Step {
     pub step: String
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// Every field is optional so partial filters work both as a query string and as a JSON body.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Filters {
-    pub query: String,
+    pub query: Option<String>,
+    #[serde(default)]
+    pub search_mode: SearchMode,
+    #[serde(default, deserialize_with = "string_list")]
     pub diets: Vec<String>,
-    pub max_ready_time: i64,
-    pub min_servings: i64,
+    pub min_ready_time: Option<i64>,
+    pub max_ready_time: Option<i64>,
+    pub min_servings: Option<i64>,
+    pub max_servings: Option<i64>,
+    #[serde(default, deserialize_with = "string_list")]
     pub cuisines: Vec<String>,
+    #[serde(default, deserialize_with = "string_list")]
     pub dish_types: Vec<String>,
-    pub max_calories: f32,
-    pub max_fats: f32,
-    pub max_carbs: f32,
-    pub max_glycemic_index: f32,
+    pub max_calories: Option<f32>,
+    pub max_fats: Option<f32>,
+    pub max_carbs: Option<f32>,
+    pub max_glycemic_index: Option<f32>,
+    #[serde(default, deserialize_with = "nutrient_ranges")]
+    pub nutrients: Vec<NutrientRange>,
+    #[serde(default, deserialize_with = "intolerance_list")]
+    pub intolerances: Vec<Intolerance>,
+    #[serde(default)]
     pub healthy: bool
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    // Stemmed, relevance-ranked search over the text index.
+    #[default]
+    Text,
+    // Case-insensitive match on the start of words, for partial input such as autocomplete.
+    Prefix
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NutrientRange {
+    pub name: String,
+    #[serde(default)]
+    pub min: Option<f32>,
+    #[serde(default)]
+    pub max: Option<f32>
+}
+
+impl FromStr for NutrientRange {
+    type Err = String;
+
+    // `name:min:max`, either bound may be left empty, e.g. `Protein:20:` or `Sugar::10`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(':').map(str::trim);
+        let name = parts.next().filter(|name| !name.is_empty())
+            .ok_or_else(|| format!("nutrient range '{}' has no name", value))?;
+
+        let mut bound = || -> Result<Option<f32>, String> {
+            match parts.next() {
+                None | Some("") => Ok(None),
+                Some(amount) => amount.parse().map(Some).map_err(|_| format!("'{}' is not a number in nutrient range '{}'", amount, value))
+            }
+        };
+
+        let range = NutrientRange { name: name.to_string(), min: bound()?, max: bound()? };
+
+        if parts.next().is_some() {
+            return Err(format!("nutrient range '{}' should look like name:min:max", value));
+        }
+
+        Ok(range)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringList {
+    Joined(String),
+    List(Vec<String>)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NutrientRangeList {
+    Joined(String),
+    List(Vec<NutrientRange>)
+}
+
+fn nutrient_ranges<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<NutrientRange>, D::Error> {
+    match NutrientRangeList::deserialize(deserializer)? {
+        NutrientRangeList::Joined(joined) => joined.split(',')
+            .filter(|range| !range.trim().is_empty())
+            .map(|range| range.parse().map_err(de::Error::custom))
+            .collect(),
+        NutrientRangeList::List(list) => Ok(list)
+    }
+}
+
+fn intolerance_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Intolerance>, D::Error> {
+    string_list(deserializer)?.iter()
+        .map(|value| value.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+// Query strings carry lists as `a,b,c` while JSON bodies use arrays; accept both.
+fn string_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let values = match StringList::deserialize(deserializer)? {
+        StringList::Joined(joined) => joined.split(',').map(|value| value.to_string()).collect(),
+        StringList::List(list) => list
+    };
+
+    Ok(values.into_iter()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect())
+}
@@ -0,0 +1,76 @@
+use std::env;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use mongodb::bson::oid::ObjectId;
+use rand::RngCore;
+use serde::{ Serialize, Deserialize };
+use sha2::Sha256;
+
+use crate::errors::AppError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    #[serde(rename = "next")]
+    Next,
+    #[serde(rename = "prev")]
+    Prev
+}
+
+// Points at the `_id` of the last (next) or first (prev) recipe of the page that was served.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Cursor {
+    pub id: ObjectId,
+    pub direction: Direction
+}
+
+pub struct CursorCodec {
+    secret: Vec<u8>
+}
+
+impl CursorCodec {
+    pub fn new(secret: Vec<u8>) -> Self {
+        CursorCodec { secret }
+    }
+
+    pub fn from_env() -> Self {
+        match env::var("CURSOR_SECRET") {
+            Ok(secret) if !secret.is_empty() => CursorCodec::new(secret.into_bytes()),
+            _ => {
+                log::warn!("CURSOR_SECRET not set, cursors will not survive a restart");
+                let mut secret = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                CursorCodec::new(secret)
+            }
+        }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    pub fn encode(&self, cursor: &Cursor) -> String {
+        let payload = serde_json::to_vec(cursor).expect("Cursor is always serializable");
+
+        let mut mac = self.mac();
+        mac.update(&payload);
+        let signature = mac.finalize().into_bytes();
+
+        format!("{}.{}", URL_SAFE_NO_PAD.encode(payload), URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    pub fn decode(&self, token: &str) -> Result<Cursor, AppError> {
+        let invalid = || AppError::validation("cursor is invalid or has been tampered with");
+
+        let (payload, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+
+        let mut mac = self.mac();
+        mac.update(&payload);
+        mac.verify_slice(&signature).map_err(|_| invalid())?;
+
+        serde_json::from_slice(&payload).map_err(|_| invalid())
+    }
+}
@@ -0,0 +1,361 @@
+use json_patch::{Patch, PatchOperation};
+use mongodb::bson::{doc, Bson, Document};
+use serde_json::Value;
+
+use crate::{errors::AppError, models::Recipe, validation::{validate_recipe, FieldError}};
+
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+// Derived on every write, so patching them directly would be overwritten anyway.
+pub const COMPUTED: [&str; 4] = ["allergens", "calories", "health_score", "fingerprint"];
+
+// Only ever set by the server.
+const MANAGED: [&str; 3] = ["_id", "version", "source"];
+
+pub enum RecipePatch {
+    Merge(Value),
+    Json(Patch)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Change {
+    Set(String),
+    Unset(String),
+    Push(String, usize)
+}
+
+// The patched recipe together with the paths the patch touched, so a backend can write just those.
+pub struct PatchedRecipe {
+    pub recipe: Recipe,
+    changes: Vec<Change>
+}
+
+impl Change {
+    fn path(&self) -> &str {
+        match self {
+            Change::Set(path) | Change::Unset(path) | Change::Push(path, _) => path
+        }
+    }
+}
+
+impl RecipePatch {
+    // Plain `application/json` is read as a merge patch, which is what a client sending a partial recipe means.
+    pub fn parse(content_type: &str, body: &[u8]) -> Result<Self, AppError> {
+        let invalid = |e: serde_json::Error| AppError::validation(format!("Invalid patch document: {}", e));
+
+        match content_type {
+            MERGE_PATCH | "application/json" => {
+                let patch: Value = serde_json::from_slice(body).map_err(invalid)?;
+                if !patch.is_object() {
+                    return Err(AppError::validation("A merge patch must be a JSON object"));
+                }
+                Ok(RecipePatch::Merge(patch))
+            },
+            JSON_PATCH => Ok(RecipePatch::Json(serde_json::from_slice(body).map_err(invalid)?)),
+            other => Err(AppError::UnsupportedMediaType(format!("{}, expected {} or {}", other, MERGE_PATCH, JSON_PATCH)))
+        }
+    }
+
+    // Applies the patch to `current` and validates the result.
+    pub fn apply(&self, current: &Recipe) -> Result<PatchedRecipe, AppError> {
+        let mut target = serde_json::to_value(Recipe { _id: None, ..current.clone() })
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        let original = target.clone();
+
+        let changes = match self {
+            RecipePatch::Merge(patch) => {
+                json_patch::merge(&mut target, patch);
+                let mut changes = Vec::new();
+                merge_changes("", patch, &mut changes);
+                changes
+            },
+            RecipePatch::Json(patch) => {
+                json_patch::patch(&mut target, patch).map_err(|e| AppError::Unprocessable(vec![FieldError {
+                    field: e.path.clone(),
+                    message: format!("operation {} failed: {}", e.operation, e.kind)
+                }]))?;
+                patch.iter().flat_map(|operation| operation_changes(operation, &target)).collect()
+            }
+        };
+
+        check_paths(&changes, &original)?;
+
+        let patched: Recipe = serde_json::from_value(target).map_err(|e| AppError::Unprocessable(vec![FieldError {
+            field: "recipe".to_string(),
+            message: e.to_string()
+        }]))?;
+
+        if patched.id != current.id {
+            return Err(AppError::Unprocessable(vec![FieldError {
+                field: "id".to_string(),
+                message: "cannot be changed".to_string()
+            }]));
+        }
+
+        validate_recipe(&patched)?;
+
+        Ok(PatchedRecipe { recipe: Recipe { _id: current._id, ..patched }, changes: collapse(changes) })
+    }
+}
+
+fn merge_changes(prefix: &str, patch: &Value, changes: &mut Vec<Change>) {
+    let Value::Object(members) = patch else {
+        return;
+    };
+
+    for (key, value) in members {
+        let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+
+        match value {
+            Value::Null => changes.push(Change::Unset(path)),
+            Value::Object(_) => merge_changes(&path, value, changes),
+            _ => changes.push(Change::Set(path))
+        }
+    }
+}
+
+fn operation_changes(operation: &PatchOperation, target: &Value) -> Vec<Change> {
+    match operation {
+        PatchOperation::Add(add) => vec![added(&add.path, target)],
+        PatchOperation::Replace(replace) => vec![Change::Set(dotted(&replace.path))],
+        PatchOperation::Remove(remove) => vec![removed(&remove.path, target)],
+        PatchOperation::Move(moved) => vec![removed(&moved.from, target), added(&moved.path, target)],
+        PatchOperation::Copy(copy) => vec![added(&copy.path, target)],
+        PatchOperation::Test(_) => Vec::new()
+    }
+}
+
+// Inserting into the middle of an array shifts its elements, which only a `$set` of the whole array expresses.
+fn added(pointer: &str, target: &Value) -> Change {
+    let (parent, last) = split_pointer(pointer);
+
+    if last == "-" {
+        Change::Push(dotted(parent), 1)
+    } else if target.pointer(parent).is_some_and(Value::is_array) {
+        Change::Set(dotted(parent))
+    } else {
+        Change::Set(dotted(pointer))
+    }
+}
+
+// Same for removing an element: `$unset` would leave a null behind in the array.
+fn removed(pointer: &str, target: &Value) -> Change {
+    let (parent, _) = split_pointer(pointer);
+
+    if target.pointer(parent).is_some_and(Value::is_array) {
+        Change::Set(dotted(parent))
+    } else {
+        Change::Unset(dotted(pointer))
+    }
+}
+
+fn split_pointer(pointer: &str) -> (&str, &str) {
+    pointer.rsplit_once('/').unwrap_or(("", pointer))
+}
+
+fn dotted(pointer: &str) -> String {
+    pointer.split('/')
+        .skip(1)
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn check_paths(changes: &[Change], original: &Value) -> Result<(), AppError> {
+    let mut errors = Vec::new();
+
+    for change in changes {
+        let field = change.path().split('.').next().unwrap_or_default();
+
+        if field.is_empty() {
+            errors.push(FieldError { field: field.to_string(), message: "the whole recipe cannot be replaced by a patch".to_string() });
+        } else if MANAGED.contains(&field) || COMPUTED.contains(&field) {
+            errors.push(FieldError { field: field.to_string(), message: "is managed by the server and cannot be patched".to_string() });
+        } else if original.get(field).is_none() && !matches!(change, Change::Unset(_)) {
+            errors.push(FieldError { field: field.to_string(), message: "is not a recipe field".to_string() });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Unprocessable(errors))
+    }
+}
+
+fn overlaps(a: &str, b: &str) -> bool {
+    a == b || b.starts_with(&format!("{}.", a)) || a.starts_with(&format!("{}.", b))
+}
+
+// MongoDB rejects an update touching a path and one of its ancestors (or the same path twice),
+// so any such pair is replaced by a `$set` of the outermost path.
+fn collapse(mut changes: Vec<Change>) -> Vec<Change> {
+    'outer: loop {
+        for i in 0..changes.len() {
+            for j in i + 1..changes.len() {
+                if overlaps(changes[i].path(), changes[j].path()) {
+                    let outer = if changes[i].path().len() <= changes[j].path().len() { i } else { j };
+                    let path = changes[outer].path().to_string();
+
+                    changes.remove(j);
+                    changes[i] = Change::Set(path);
+                    continue 'outer;
+                }
+            }
+        }
+
+        return changes;
+    }
+}
+
+fn lookup<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut segments = path.split('.');
+    let mut current = document.get(segments.next()?)?;
+
+    for segment in segments {
+        current = match current {
+            Bson::Document(document) => document.get(segment)?,
+            Bson::Array(array) => array.get(segment.parse::<usize>().ok()?)?,
+            _ => return None
+        };
+    }
+
+    Some(current)
+}
+
+impl PatchedRecipe {
+    // The MongoDB update that turns the stored recipe into the patched one. Values always come from the patched
+    // document, so they carry the same types a full write would store.
+    pub fn update_document(&self) -> Document {
+        let patched = self.recipe.to_document();
+        let mut set = Document::new();
+        let mut unset = Document::new();
+        let mut push = Document::new();
+
+        for change in &self.changes {
+            match (change, lookup(&patched, change.path())) {
+                (Change::Push(path, count), Some(Bson::Array(array))) => {
+                    let added: Vec<Bson> = array[array.len().saturating_sub(*count)..].to_vec();
+                    push.insert(path.clone(), doc! { "$each": added });
+                },
+                (Change::Unset(path), None) => {
+                    unset.insert(path.clone(), "");
+                },
+                (_, Some(value)) => {
+                    set.insert(change.path().to_string(), value.clone());
+                },
+                (_, None) => {
+                    unset.insert(change.path().to_string(), "");
+                }
+            }
+        }
+
+        for field in COMPUTED {
+            if let Some(value) = patched.get(field) {
+                set.insert(field, value.clone());
+            }
+        }
+
+        let mut update = doc! { "$set": set };
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+        if !push.is_empty() {
+            update.insert("$push", push);
+        }
+        update
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::fixture_recipes;
+
+    fn current() -> Recipe {
+        fixture_recipes().into_iter().find(|recipe| recipe.id == "716429").unwrap()
+    }
+
+    fn apply(content_type: &str, patch: Value) -> Result<PatchedRecipe, AppError> {
+        RecipePatch::parse(content_type, patch.to_string().as_bytes())?.apply(&current())
+    }
+
+    fn rejected_fields(content_type: &str, patch: Value) -> Vec<String> {
+        match apply(content_type, patch) {
+            Err(AppError::Unprocessable(errors)) => errors.into_iter().map(|error| error.field).collect(),
+            Err(e) => panic!("expected a 422, got {}", e),
+            Ok(_) => panic!("expected a 422, got a patched recipe")
+        }
+    }
+
+    #[test]
+    fn json_patch_becomes_set_and_push() {
+        let patched = apply(JSON_PATCH, json!([
+            { "op": "replace", "path": "/title", "value": "Cauliflower Pasta" },
+            { "op": "add", "path": "/diets/-", "value": "vegetarian" },
+            { "op": "remove", "path": "/instructions/2" },
+            { "op": "test", "path": "/servings", "value": 2 }
+        ])).ok().unwrap();
+
+        assert_eq!(patched.changes, [Change::Set("title".into()), Change::Push("diets".into(), 1), Change::Set("instructions".into())]);
+
+        let update = patched.update_document();
+        let set = update.get_document("$set").unwrap();
+        assert_eq!(set.get_str("title").unwrap(), "Cauliflower Pasta");
+        assert_eq!(set.get_array("instructions").unwrap().len(), 2);
+        assert!(COMPUTED.iter().all(|field| set.contains_key(field)));
+        assert_eq!(update.get_document("$push").unwrap(), &doc! { "diets": { "$each": ["vegetarian"] } });
+        assert!(!update.contains_key("$unset"));
+    }
+
+    #[test]
+    fn merge_patch_nulls_become_unset() {
+        let patched = apply(MERGE_PATCH, json!({ "servings": 4, "legacy_rating": null })).ok().unwrap();
+        let update = patched.update_document();
+
+        assert_eq!(update.get_document("$set").unwrap().get_i64("servings").unwrap(), 4);
+        assert_eq!(update.get_document("$unset").unwrap(), &doc! { "legacy_rating": "" });
+    }
+
+    #[test]
+    fn overlapping_paths_collapse_to_the_outermost() {
+        let patched = apply(JSON_PATCH, json!([
+            { "op": "replace", "path": "/instructions/0/step", "value": "Roast the cauliflower until crisp." },
+            { "op": "remove", "path": "/instructions/2" }
+        ])).ok().unwrap();
+
+        assert_eq!(patched.changes, [Change::Set("instructions".into())]);
+    }
+
+    #[test]
+    fn rejects_server_managed_unknown_and_whole_recipe_paths() {
+        let fields = rejected_fields(JSON_PATCH, json!([
+            { "op": "replace", "path": "/version", "value": 9 },
+            { "op": "add", "path": "/allergens/-", "value": "Peanut" },
+            { "op": "add", "path": "/rating", "value": 5 }
+        ]));
+        assert_eq!(fields, ["version", "allergens", "rating"]);
+
+        let recipe = serde_json::to_value(current()).unwrap();
+        assert_eq!(rejected_fields(JSON_PATCH, json!([{ "op": "replace", "path": "", "value": recipe }])), [""]);
+        assert_eq!(rejected_fields(MERGE_PATCH, json!({ "source": "import" })), ["source"]);
+    }
+
+    #[test]
+    fn reports_failed_operations_changed_ids_and_invalid_results() {
+        assert_eq!(rejected_fields(JSON_PATCH, json!([{ "op": "remove", "path": "/nutrition/missing" }])), ["/nutrition/missing"]);
+        assert_eq!(rejected_fields(MERGE_PATCH, json!({ "id": "other" })), ["id"]);
+        assert_eq!(rejected_fields(MERGE_PATCH, json!({ "servings": 0, "title": " " })), ["title", "servings"]);
+        assert_eq!(rejected_fields(MERGE_PATCH, json!({ "servings": "four" })), ["recipe"]);
+    }
+
+    #[test]
+    fn parses_by_content_type() {
+        assert!(matches!(RecipePatch::parse("application/json", b"{}"), Ok(RecipePatch::Merge(_))));
+        assert!(matches!(RecipePatch::parse(MERGE_PATCH, b"[]"), Err(AppError::Validation { .. })));
+        assert!(matches!(RecipePatch::parse("text/plain", b"{}"), Err(AppError::UnsupportedMediaType(_))));
+    }
+}
@@ -0,0 +1,295 @@
+use std::{collections::HashMap, env, future::{ready, Ready}, rc::Rc, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use actix_web::{
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue, RETRY_AFTER},
+    Error,
+    HttpMessage,
+    ResponseError
+};
+use async_trait::async_trait;
+use futures::future::LocalBoxFuture;
+use thiserror::Error;
+
+use crate::{auth::{ApiKey, Scope}, errors::AppError};
+
+const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+
+#[derive(Error, Debug)]
+pub enum RateLimitError {
+    #[error("Rate limit backend error: {0}")]
+    Backend(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    pub capacity: u32,
+    pub period: Duration
+}
+
+impl Quota {
+    fn refill_per_second(&self) -> f64 {
+        self.capacity as f64 / self.period.as_secs_f64()
+    }
+
+    // Parses `<requests>/<seconds>`, e.g. `120/60`.
+    fn parse(value: &str) -> Option<Quota> {
+        let (capacity, seconds) = value.trim().split_once('/')?;
+        let capacity: u32 = capacity.trim().parse().ok()?;
+        let seconds: u64 = seconds.trim().parse().ok()?;
+
+        if capacity == 0 || seconds == 0 {
+            return None;
+        }
+
+        Some(Quota { capacity, period: Duration::from_secs(seconds) })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    pub read: Quota,
+    pub write: Quota,
+    pub admin: Quota
+}
+
+impl RateLimits {
+    pub fn from_env() -> Self {
+        let quota = |var: &str, capacity: u32| env::var(var)
+            .ok()
+            .and_then(|value| Quota::parse(&value))
+            .unwrap_or(Quota { capacity, period: Duration::from_secs(60) });
+
+        RateLimits {
+            read: quota("RATE_LIMIT_RECIPES_READ", 120),
+            write: quota("RATE_LIMIT_RECIPES_WRITE", 30),
+            admin: quota("RATE_LIMIT_ADMIN", 300)
+        }
+    }
+
+    // A key is throttled by its most privileged scope.
+    fn for_key(&self, key: &ApiKey) -> Quota {
+        if key.has_scope(Scope::Admin) {
+            self.admin
+        } else if key.has_scope(Scope::RecipesWrite) {
+            self.write
+        } else {
+            self.read
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decision {
+    pub allowed: bool,
+    pub remaining: u32,
+    pub retry_after: Duration
+}
+
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    async fn acquire(&self, key: &str, quota: Quota) -> Result<Decision, RateLimitError>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant
+}
+
+#[derive(Default)]
+pub struct InMemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, Bucket>>
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn acquire(&self, key: &str, quota: Quota) -> Result<Decision, RateLimitError> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().map_err(|e| RateLimitError::Backend(e.to_string()))?;
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: quota.capacity as f64,
+            updated_at: now
+        });
+
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * quota.refill_per_second()).min(quota.capacity as f64);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(Decision {
+                allowed: true,
+                remaining: bucket.tokens.floor() as u32,
+                retry_after: Duration::ZERO
+            })
+        } else {
+            Ok(Decision {
+                allowed: false,
+                remaining: 0,
+                retry_after: Duration::from_secs_f64((1.0 - bucket.tokens) / quota.refill_per_second())
+            })
+        }
+    }
+}
+
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    limits: RateLimits
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>, limits: RateLimits) -> Self {
+        RateLimiter { store, limits }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            store: self.store.clone(),
+            limits: self.limits
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    store: Arc<dyn RateLimitStore>,
+    limits: RateLimits
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.store.clone();
+        let limits = self.limits;
+
+        Box::pin(async move {
+            let key = req.extensions().get::<ApiKey>().cloned();
+
+            // Unauthenticated requests never reach here behind ApiKeyAuth; let them through untouched otherwise.
+            let Some(key) = key else {
+                return Ok(service.call(req).await?.map_into_left_body());
+            };
+
+            let quota = limits.for_key(&key);
+
+            let decision = match store.acquire(&key.id, quota).await {
+                Ok(decision) => decision,
+                Err(e) => {
+                    log::warn!("Rate limiter unavailable, allowing request: {}", e);
+                    return Ok(service.call(req).await?.map_into_left_body());
+                }
+            };
+
+            if !decision.allowed {
+                let retry_after = decision.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+                let mut response = AppError::RateLimited.error_response();
+                let headers = response.headers_mut();
+                headers.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+                headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(quota.capacity));
+                headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(0));
+
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let mut res = service.call(req).await?;
+            let headers = res.headers_mut();
+            headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(quota.capacity));
+            headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(decision.remaining));
+
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::{header, StatusCode}, test::{call_service, init_service, TestRequest}, web, App, HttpResponse};
+
+    use super::*;
+    use crate::auth::{self, ApiKeyAuth, ApiKeyStore, InMemoryApiKeyStore};
+
+    fn limits(capacity: u32, period: Duration) -> RateLimits {
+        let quota = Quota { capacity, period };
+        RateLimits { read: quota, write: quota, admin: quota }
+    }
+
+    #[actix_web::test]
+    async fn throttles_once_the_quota_runs_out_until_it_refills() {
+        let keys: Arc<dyn ApiKeyStore> = Arc::new(InMemoryApiKeyStore::default());
+        auth::bootstrap(keys.as_ref(), "test-key").await.unwrap();
+        let app = init_service(App::new()
+            .wrap(RateLimiter::new(Arc::new(InMemoryRateLimitStore::default()), limits(3, Duration::from_millis(600))))
+            .wrap(ApiKeyAuth::new(keys))
+            .route("/recipes", web::get().to(HttpResponse::Ok))
+        ).await;
+        let request = || TestRequest::get().uri("/recipes").insert_header((header::AUTHORIZATION, "test-key")).to_request();
+        let header = |response: &ServiceResponse<_>, name| response.headers().get(name).unwrap().to_str().unwrap().to_string();
+
+        for remaining in ["2", "1", "0"] {
+            let response = call_service(&app, request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(header(&response, X_RATELIMIT_LIMIT), "3");
+            assert_eq!(header(&response, X_RATELIMIT_REMAINING), remaining);
+        }
+
+        let response = call_service(&app, request()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&response, RETRY_AFTER), "1");
+        assert_eq!(header(&response, X_RATELIMIT_REMAINING), "0");
+
+        // A third of the period buys back one request.
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let response = call_service(&app, request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, X_RATELIMIT_REMAINING), "0");
+    }
+
+    #[tokio::test]
+    async fn keys_have_their_own_buckets_and_refill_to_capacity() {
+        let store = InMemoryRateLimitStore::default();
+        let quota = Quota { capacity: 2, period: Duration::from_millis(100) };
+
+        assert!(store.acquire("a", quota).await.unwrap().allowed);
+        assert!(store.acquire("a", quota).await.unwrap().allowed);
+        let denied = store.acquire("a", quota).await.unwrap();
+        assert!(!denied.allowed);
+        assert!(denied.retry_after > Duration::ZERO && denied.retry_after <= Duration::from_millis(50));
+        assert_eq!(store.acquire("b", quota).await.unwrap().remaining, 1);
+
+        // Idle for longer than a whole period, the bucket is full again but no fuller.
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(store.acquire("a", quota).await.unwrap().remaining, 1);
+    }
+
+    #[test]
+    fn parses_quotas() {
+        let quota = Quota::parse(" 120 / 60 ").unwrap();
+        assert_eq!((quota.capacity, quota.period), (120, Duration::from_secs(60)));
+        assert!(Quota::parse("0/60").is_none());
+        assert!(Quota::parse("120").is_none());
+    }
+}
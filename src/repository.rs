@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use async_trait::async_trait;
+use mongodb::{bson, Collection};
+use thiserror::Error;
+
+use crate::{db, models::{Filters, Recipe}, pagination::Cursor, patch::PatchedRecipe, sort::SortKey};
+
+#[derive(Error, Debug)]
+pub enum RecipeError {
+    #[error("Database error")]
+    DatabaseError(#[from] mongodb::error::Error),
+    #[error("Serialization error")]
+    SerializationError(#[from] bson::de::Error),
+    #[error("SQLite error")]
+    SqliteError(#[from] sqlx::Error),
+    #[error("Recipe {0} not found")]
+    NotFound(String),
+    #[error("Write failed: {0}")]
+    WriteFailed(String),
+    #[error("Recipe {0} already exists")]
+    AlreadyExists(String),
+    #[error("Recipe {0} does not match the expected version")]
+    VersionMismatch(String),
+}
+
+pub struct Page {
+    pub recipes: Vec<Recipe>,
+    pub total_items: u64
+}
+
+pub enum InsertOutcome {
+    Inserted,
+    Failed(RecipeError),
+    NotExecuted
+}
+
+pub struct KeysetPage {
+    pub recipes: Vec<Recipe>,
+    pub has_more: bool
+}
+
+// Everything the handlers need from recipe storage. `versions` comes from an `If-Match` header, `None` meaning
+// any version will do, and every write bumps the stored version. Allergens are always recomputed on write rather
+// than taken from the recipe given.
+#[async_trait]
+pub trait RecipeRepository: Send + Sync {
+    // Fails with `AlreadyExists` rather than overwriting.
+    async fn create(&self, recipe: &Recipe) -> Result<Recipe, RecipeError>;
+    // One `create` after another, stopping at the first failure if `ordered`. MongoDB does it in one `insert_many`.
+    async fn create_many(&self, recipes: &[Recipe], ordered: bool) -> Vec<InsertOutcome> {
+        let mut outcomes = Vec::new();
+        let mut failed = false;
+
+        for recipe in recipes {
+            if failed && ordered {
+                outcomes.push(InsertOutcome::NotExecuted);
+                continue;
+            }
+
+            match self.create(recipe).await {
+                Ok(_) => outcomes.push(InsertOutcome::Inserted),
+                Err(e) => {
+                    failed = true;
+                    outcomes.push(InsertOutcome::Failed(e));
+                }
+            }
+        }
+
+        outcomes
+    }
+
+    // Used by the import, and fails with `AlreadyExists` rather than overwrite a recipe a user created.
+    async fn upsert(&self, recipe: &Recipe) -> Result<(), RecipeError>;
+    async fn read(&self, id: &str) -> Result<Option<Recipe>, RecipeError>;
+    // Tells a stale version (`VersionMismatch`) apart from a recipe that isn't there at all (`NotFound`), as does `delete`.
+    async fn update(&self, id: &str, recipe: &Recipe, versions: Option<&[i64]>) -> Result<Recipe, RecipeError>;
+    // Only applies to the stored recipe if it is still at `version`, the one the patch was computed against.
+    async fn patch(&self, id: &str, patched: &PatchedRecipe, version: i64) -> Result<Recipe, RecipeError>;
+    async fn delete(&self, id: &str, versions: Option<&[i64]>) -> Result<(), RecipeError>;
+    // Listing everything is filtering with `Filters::default()`. Text searches rank by relevance unless `sort`
+    // asks for a specific order, and ties always go to the recipe stored first.
+    async fn filter(&self, filters: Filters, sort: &[SortKey], page: usize, page_size: usize) -> Result<Page, RecipeError>;
+    async fn filter_keyset(&self, filters: Filters, cursor: Option<&Cursor>, page_size: usize) -> Result<KeysetPage, RecipeError>;
+    async fn count(&self, filters: Filters) -> Result<u64, RecipeError>;
+    // Best candidates first (fewest ingredients matching none of the terms), so a `limit` cut keeps them.
+    async fn find_by_ingredient_terms(&self, terms: &[String], limit: usize) -> Result<Vec<Recipe>, RecipeError>;
+    async fn find_duplicates(&self, fingerprints: &[String]) -> Result<HashMap<String, String>, RecipeError>;
+
+    async fn find_duplicate(&self, recipe: &Recipe) -> Result<Option<String>, RecipeError> {
+        Ok(self.find_duplicates(&[recipe.fingerprint()]).await?.into_values().next())
+    }
+}
+
+pub struct MongoRecipeRepository {
+    collection: Collection<Recipe>
+}
+
+impl MongoRecipeRepository {
+    pub fn new(collection: Collection<Recipe>) -> Self {
+        MongoRecipeRepository { collection }
+    }
+}
+
+#[async_trait]
+impl RecipeRepository for MongoRecipeRepository {
+    async fn create(&self, recipe: &Recipe) -> Result<Recipe, RecipeError> {
+        db::insert_recipe(&self.collection, recipe).await
+    }
+
+    async fn create_many(&self, recipes: &[Recipe], ordered: bool) -> Vec<InsertOutcome> {
+        db::insert_recipes(&self.collection, recipes, ordered).await
+    }
+
+    async fn upsert(&self, recipe: &Recipe) -> Result<(), RecipeError> {
+        db::upsert_recipe(&self.collection, recipe).await
+    }
+
+    async fn read(&self, id: &str) -> Result<Option<Recipe>, RecipeError> {
+        Ok(db::read_recipe(&self.collection, id).await?)
+    }
+
+    async fn update(&self, id: &str, recipe: &Recipe, versions: Option<&[i64]>) -> Result<Recipe, RecipeError> {
+        db::update_recipe(&self.collection, id, recipe.to_document(), versions).await
+    }
+
+    async fn patch(&self, id: &str, patched: &PatchedRecipe, version: i64) -> Result<Recipe, RecipeError> {
+        db::patch_recipe(&self.collection, id, patched.update_document(), Some(&[version])).await
+    }
+
+    async fn delete(&self, id: &str, versions: Option<&[i64]>) -> Result<(), RecipeError> {
+        db::delete_recipe(&self.collection, id, versions).await
+    }
+
+    async fn filter(&self, filters: Filters, sort: &[SortKey], page: usize, page_size: usize) -> Result<Page, RecipeError> {
+        db::filter_recipes(&self.collection, filters, sort, page, page_size).await
+    }
+
+    async fn filter_keyset(&self, filters: Filters, cursor: Option<&Cursor>, page_size: usize) -> Result<KeysetPage, RecipeError> {
+        db::filter_recipes_keyset(&self.collection, filters, cursor, page_size).await
+    }
+
+    async fn count(&self, filters: Filters) -> Result<u64, RecipeError> {
+        db::count_recipes(&self.collection, filters).await
+    }
+
+    async fn find_by_ingredient_terms(&self, terms: &[String], limit: usize) -> Result<Vec<Recipe>, RecipeError> {
+        db::find_by_ingredient_terms(&self.collection, terms, limit as i64).await
+    }
+
+    async fn find_duplicates(&self, fingerprints: &[String]) -> Result<HashMap<String, String>, RecipeError> {
+        Ok(db::find_duplicates(&self.collection, fingerprints).await?)
+    }
+}
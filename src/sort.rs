@@ -0,0 +1,99 @@
+use mongodb::bson::{doc, Document};
+
+use crate::errors::AppError;
+
+const MAX_SORT_KEYS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    ReadyTime,
+    Servings,
+    Calories,
+    HealthScore,
+    Title,
+    Recency
+}
+
+impl SortField {
+    const ALLOWED: [(&'static str, SortField); 6] = [
+        ("ready_time", SortField::ReadyTime),
+        ("servings", SortField::Servings),
+        ("calories", SortField::Calories),
+        ("health_score", SortField::HealthScore),
+        ("title", SortField::Title),
+        ("recency", SortField::Recency)
+    ];
+
+    fn parse(name: &str) -> Option<SortField> {
+        SortField::ALLOWED.iter()
+            .find(|(allowed, _)| *allowed == name)
+            .map(|(_, field)| *field)
+    }
+
+    pub fn document_field(&self) -> &'static str {
+        match self {
+            SortField::ReadyTime => "ready_in_minutes",
+            SortField::Servings => "servings",
+            SortField::Calories => "calories",
+            SortField::HealthScore => "health_score",
+            SortField::Title => "title",
+            SortField::Recency => "_id"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool
+}
+
+// Accepts a comma separated list such as `-calories,title` or `ready_time:asc,recency:desc`.
+pub fn parse_sort(value: &str) -> Result<Vec<SortKey>, AppError> {
+    let mut keys: Vec<SortKey> = Vec::new();
+
+    for part in value.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let (name, descending) = match part.split_once(':') {
+            Some((name, "asc")) => (name, false),
+            Some((name, "desc")) => (name, true),
+            Some((_, order)) => return Err(AppError::validation(format!("Unknown sort order '{}', expected asc or desc", order))),
+            None => match part.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (part, false)
+            }
+        };
+
+        let field = SortField::parse(name).ok_or_else(|| AppError::validation(format!(
+            "Cannot sort by '{}', expected one of: {}",
+            name,
+            SortField::ALLOWED.iter().map(|(allowed, _)| *allowed).collect::<Vec<_>>().join(", ")
+        )))?;
+
+        if keys.iter().any(|key| key.field == field) {
+            return Err(AppError::validation(format!("Sort field '{}' given more than once", name)));
+        }
+
+        keys.push(SortKey { field, descending });
+    }
+
+    if keys.len() > MAX_SORT_KEYS {
+        return Err(AppError::validation(format!("At most {} sort keys are allowed", MAX_SORT_KEYS)));
+    }
+
+    Ok(keys)
+}
+
+// `_id` always closes the sort so that ties come back in a stable order between pages.
+pub fn sort_document(keys: &[SortKey]) -> Document {
+    let mut sort = doc! {};
+
+    for key in keys {
+        sort.insert(key.field.document_field(), if key.descending { -1 } else { 1 });
+    }
+
+    if !sort.contains_key("_id") {
+        sort.insert("_id", 1);
+    }
+
+    sort
+}
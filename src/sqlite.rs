@@ -0,0 +1,729 @@
+use std::{collections::HashMap, str::FromStr};
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+use sqlx::{sqlite::{SqliteConnectOptions, SqlitePoolOptions}, QueryBuilder, Row, Sqlite, SqlitePool, Transaction};
+
+use crate::{
+    allergens::Intolerance,
+    filter_builder::{self, Constraint, Count, ElementList, Flag, TagList},
+    models::{Filters, Ingredient, Nutrient, Nutrition, Property, Recipe, RecipeSource, Step},
+    pagination::{Cursor, Direction},
+    patch::PatchedRecipe,
+    repository::{KeysetPage, Page, RecipeError, RecipeRepository},
+    sort::{SortField, SortKey}
+};
+
+pub const DEFAULT_URL: &str = "sqlite://sqlite.db";
+
+const CUISINE: &str = "cuisine";
+const DISH_TYPE: &str = "dish_type";
+const DIET: &str = "diet";
+const ALLERGEN: &str = "allergen";
+
+pub struct SqliteRecipeRepository {
+    pool: SqlitePool
+}
+
+// Creates the database file if needed and brings its schema up to date.
+pub async fn connect(url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(url)?
+        .create_if_missing(true)
+        .foreign_keys(true);
+
+    let pool = SqlitePoolOptions::new().connect_with(options).await?;
+    sqlx::migrate!().run(&pool).await?;
+
+    Ok(pool)
+}
+
+impl SqliteRecipeRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        SqliteRecipeRepository { pool }
+    }
+
+    async fn load(&self, pks: &[i64]) -> Result<Vec<Recipe>, RecipeError> {
+        if pks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let rows = in_list("SELECT * FROM recipes WHERE pk IN ", pks, "").build().fetch_all(&self.pool).await?;
+
+        let mut recipes: HashMap<i64, Recipe> = HashMap::new();
+        for row in rows {
+            let object_id: String = row.try_get("object_id")?;
+            let source: String = row.try_get("source")?;
+
+            recipes.insert(row.try_get("pk")?, Recipe {
+                _id: ObjectId::parse_str(&object_id).ok(),
+                id: row.try_get("id")?,
+                title: row.try_get("title")?,
+                summary: row.try_get("summary")?,
+                image: row.try_get("image")?,
+                vegetarian: row.try_get("vegetarian")?,
+                vegan: row.try_get("vegan")?,
+                gluten_free: row.try_get("gluten_free")?,
+                dairy_free: row.try_get("dairy_free")?,
+                ready_in_minutes: row.try_get("ready_in_minutes")?,
+                servings: row.try_get("servings")?,
+                ingredients: Vec::new(),
+                nutrition: Nutrition { nutrients: Vec::new(), properties: Vec::new() },
+                cuisines: Vec::new(),
+                dish_types: Vec::new(),
+                diets: Vec::new(),
+                instructions: Vec::new(),
+                allergens: Vec::new(),
+                version: row.try_get("version")?,
+                source: if source == RecipeSource::User.name() { RecipeSource::User } else { RecipeSource::Spoonacular }
+            });
+        }
+
+        let order = " ORDER BY recipe_pk, position";
+
+        for row in in_list("SELECT * FROM recipe_ingredients WHERE recipe_pk IN ", pks, order).build().fetch_all(&self.pool).await? {
+            if let Some(recipe) = recipes.get_mut(&row.try_get("recipe_pk")?) {
+                recipe.ingredients.push(Ingredient {
+                    name: row.try_get("name")?,
+                    amount: row.try_get::<f64, _>("amount")? as f32,
+                    unit: row.try_get("unit")?
+                });
+            }
+        }
+
+        for row in in_list("SELECT * FROM recipe_nutrients WHERE recipe_pk IN ", pks, order).build().fetch_all(&self.pool).await? {
+            if let Some(recipe) = recipes.get_mut(&row.try_get("recipe_pk")?) {
+                recipe.nutrition.nutrients.push(Nutrient {
+                    name: row.try_get("name")?,
+                    amount: row.try_get::<f64, _>("amount")? as f32,
+                    unit: row.try_get("unit")?
+                });
+            }
+        }
+
+        for row in in_list("SELECT * FROM recipe_properties WHERE recipe_pk IN ", pks, order).build().fetch_all(&self.pool).await? {
+            if let Some(recipe) = recipes.get_mut(&row.try_get("recipe_pk")?) {
+                recipe.nutrition.properties.push(Property {
+                    name: row.try_get("name")?,
+                    amount: row.try_get::<f64, _>("amount")? as f32
+                });
+            }
+        }
+
+        for row in in_list("SELECT * FROM recipe_steps WHERE recipe_pk IN ", pks, order).build().fetch_all(&self.pool).await? {
+            if let Some(recipe) = recipes.get_mut(&row.try_get("recipe_pk")?) {
+                recipe.instructions.push(Step { number: row.try_get("number")?, step: row.try_get("step")? });
+            }
+        }
+
+        for row in in_list("SELECT * FROM recipe_tags WHERE recipe_pk IN ", pks, " ORDER BY recipe_pk, kind, position").build().fetch_all(&self.pool).await? {
+            if let Some(recipe) = recipes.get_mut(&row.try_get("recipe_pk")?) {
+                let kind: String = row.try_get("kind")?;
+                let value: String = row.try_get("value")?;
+
+                match kind.as_str() {
+                    CUISINE => recipe.cuisines.push(value),
+                    DISH_TYPE => recipe.dish_types.push(value),
+                    DIET => recipe.diets.push(value),
+                    ALLERGEN => recipe.allergens.extend(Intolerance::from_str(&value).ok()),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(pks.iter().filter_map(|pk| recipes.remove(pk)).collect())
+    }
+
+    async fn load_one(&self, pk: i64) -> Result<Recipe, RecipeError> {
+        self.load(&[pk]).await?.pop().ok_or_else(|| RecipeError::NotFound(pk.to_string()))
+    }
+
+    async fn pks(&self, mut query: QueryBuilder<'_, Sqlite>) -> Result<Vec<i64>, RecipeError> {
+        let rows = query.build().fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| row.try_get("pk")).collect::<Result<_, _>>()?)
+    }
+
+    async fn missing(&self, id: &str, versions: Option<&[i64]>) -> RecipeError {
+        if versions.is_none() {
+            return RecipeError::NotFound(id.to_string());
+        }
+
+        match sqlx::query("SELECT 1 FROM recipes WHERE id = ?").bind(id).fetch_optional(&self.pool).await {
+            Ok(Some(_)) => RecipeError::VersionMismatch(id.to_string()),
+            Ok(None) => RecipeError::NotFound(id.to_string()),
+            Err(e) => e.into()
+        }
+    }
+}
+
+fn in_list<'a, T>(prefix: &str, values: &'a [T], suffix: &str) -> QueryBuilder<'a, Sqlite>
+where
+    T: sqlx::Encode<'a, Sqlite> + sqlx::Type<Sqlite> + Send + Sync + 'a
+{
+    let mut query = QueryBuilder::new(prefix);
+    push_in(&mut query, values);
+    query.push(suffix);
+    query
+}
+
+fn push_in<'a, T>(query: &mut QueryBuilder<'a, Sqlite>, values: &'a [T])
+where
+    T: sqlx::Encode<'a, Sqlite> + sqlx::Type<Sqlite> + Send + Sync + 'a
+{
+    query.push("(");
+    let mut separated = query.separated(", ");
+    for value in values {
+        separated.push_bind(value);
+    }
+    query.push(")");
+}
+
+// `LIKE` patterns matching `term` at the start of a word, the closest SQLite gets to the `\bterm` regexes used with MongoDB.
+fn word_prefix_patterns(term: &str) -> [String; 2] {
+    let escaped = term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    [format!("{}%", escaped), format!("% {}%", escaped)]
+}
+
+fn push_word_prefix<'a>(query: &mut QueryBuilder<'a, Sqlite>, column: &str, term: &str) {
+    let [start, inner] = word_prefix_patterns(term);
+    query.push(format!("({} LIKE ", column)).push_bind(start)
+        .push(format!(" ESCAPE '\\' OR {} LIKE ", column)).push_bind(inner)
+        .push(" ESCAPE '\\')");
+}
+
+// FTS5 has its own query syntax, so each term is quoted and any of them may match.
+fn match_expression(terms: &str) -> String {
+    terms.split_whitespace()
+        .map(|term| format!("\"{}\"", term))
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+fn text_search(constraints: &[Constraint]) -> Option<String> {
+    constraints.iter().find_map(|constraint| match constraint {
+        Constraint::Text(terms) => Some(match_expression(terms)),
+        _ => None
+    })
+}
+
+fn push_range<'a, T>(query: &mut QueryBuilder<'a, Sqlite>, column: &str, min: Option<T>, max: Option<T>)
+where
+    T: sqlx::Encode<'a, Sqlite> + sqlx::Type<Sqlite> + Send + 'a
+{
+    if let Some(min) = min {
+        query.push(format!(" AND {} >= ", column)).push_bind(min);
+    }
+    if let Some(max) = max {
+        query.push(format!(" AND {} <= ", column)).push_bind(max);
+    }
+}
+
+// `filter_builder::constraints`, expressed over the normalized tables.
+fn push_filter(query: &mut QueryBuilder<'_, Sqlite>, constraints: &[Constraint]) {
+    query.push(" WHERE 1 = 1");
+
+    for constraint in constraints {
+        match constraint {
+            Constraint::Text(terms) => {
+                query.push(" AND r.pk IN (SELECT rowid FROM recipe_search WHERE recipe_search MATCH ").push_bind(match_expression(terms)).push(")");
+            },
+            Constraint::Prefix(term) => {
+                query.push(" AND (");
+                push_word_prefix(query, "r.title", term);
+                query.push(" OR EXISTS (SELECT 1 FROM recipe_ingredients i WHERE i.recipe_pk = r.pk AND ");
+                push_word_prefix(query, "i.name", term);
+                query.push("))");
+            },
+            Constraint::AnyTag(list, values) => {
+                let kind = match list {
+                    TagList::Diets => DIET,
+                    TagList::Cuisines => CUISINE,
+                    TagList::DishTypes => DISH_TYPE
+                };
+                query.push(" AND EXISTS (SELECT 1 FROM recipe_tags t WHERE t.recipe_pk = r.pk AND t.kind = ").push_bind(kind).push(" AND t.value IN (");
+                let mut separated = query.separated(", ");
+                for value in values {
+                    separated.push_bind(value.clone());
+                }
+                query.push("))");
+            },
+            Constraint::Range(count, min, max) => {
+                let column = match count {
+                    Count::ReadyInMinutes => "r.ready_in_minutes",
+                    Count::Servings => "r.servings"
+                };
+                push_range(query, column, *min, *max);
+            },
+            Constraint::Element(list, name, min, max) => {
+                let table = match list {
+                    ElementList::Nutrients => "recipe_nutrients",
+                    ElementList::Properties => "recipe_properties"
+                };
+                query.push(format!(" AND EXISTS (SELECT 1 FROM {} e WHERE e.recipe_pk = r.pk AND e.name = ", table)).push_bind(name.clone());
+                push_range(query, "e.amount", *min, *max);
+                query.push(")");
+            },
+            Constraint::Flag(Flag::DairyFree) => { query.push(" AND r.dairy_free = 1"); },
+            Constraint::Flag(Flag::GlutenFree) => { query.push(" AND r.gluten_free = 1"); },
+            Constraint::NoAllergens(intolerances) => {
+                query.push(" AND NOT EXISTS (SELECT 1 FROM recipe_tags t WHERE t.recipe_pk = r.pk AND t.kind = ").push_bind(ALLERGEN).push(" AND t.value IN (");
+                let mut separated = query.separated(", ");
+                for intolerance in intolerances {
+                    separated.push_bind(intolerance.name());
+                }
+                query.push("))");
+            }
+        }
+    }
+}
+
+fn sort_column(field: SortField) -> &'static str {
+    match field {
+        SortField::ReadyTime => "r.ready_in_minutes",
+        SortField::Servings => "r.servings",
+        SortField::Calories => "r.calories",
+        SortField::HealthScore => "r.health_score",
+        SortField::Title => "r.title",
+        SortField::Recency => "r.object_id"
+    }
+}
+
+async fn insert_children(tx: &mut Transaction<'_, Sqlite>, pk: i64, recipe: &Recipe) -> Result<(), sqlx::Error> {
+    for (position, ingredient) in recipe.ingredients.iter().enumerate() {
+        sqlx::query("INSERT INTO recipe_ingredients (recipe_pk, position, name, amount, unit) VALUES (?, ?, ?, ?, ?)")
+            .bind(pk).bind(position as i64).bind(&ingredient.name).bind(ingredient.amount as f64).bind(&ingredient.unit)
+            .execute(&mut **tx).await?;
+    }
+
+    for (position, nutrient) in recipe.nutrition.nutrients.iter().enumerate() {
+        sqlx::query("INSERT INTO recipe_nutrients (recipe_pk, position, name, amount, unit) VALUES (?, ?, ?, ?, ?)")
+            .bind(pk).bind(position as i64).bind(&nutrient.name).bind(nutrient.amount as f64).bind(&nutrient.unit)
+            .execute(&mut **tx).await?;
+    }
+
+    for (position, property) in recipe.nutrition.properties.iter().enumerate() {
+        sqlx::query("INSERT INTO recipe_properties (recipe_pk, position, name, amount) VALUES (?, ?, ?, ?)")
+            .bind(pk).bind(position as i64).bind(&property.name).bind(property.amount as f64)
+            .execute(&mut **tx).await?;
+    }
+
+    for (position, step) in recipe.instructions.iter().enumerate() {
+        sqlx::query("INSERT INTO recipe_steps (recipe_pk, position, number, step) VALUES (?, ?, ?, ?)")
+            .bind(pk).bind(position as i64).bind(step.number).bind(&step.step)
+            .execute(&mut **tx).await?;
+    }
+
+    let allergens: Vec<String> = crate::allergens::detect(recipe).iter().map(|intolerance| intolerance.name().to_string()).collect();
+    let tags = [(CUISINE, &recipe.cuisines), (DISH_TYPE, &recipe.dish_types), (DIET, &recipe.diets), (ALLERGEN, &allergens)];
+
+    for (kind, values) in tags {
+        for (position, value) in values.iter().enumerate() {
+            sqlx::query("INSERT INTO recipe_tags (recipe_pk, kind, position, value) VALUES (?, ?, ?, ?)")
+                .bind(pk).bind(kind).bind(position as i64).bind(value)
+                .execute(&mut **tx).await?;
+        }
+    }
+
+    let ingredients: Vec<&str> = recipe.ingredients.iter().map(|ingredient| ingredient.name.as_str()).collect();
+    let instructions: Vec<&str> = recipe.instructions.iter().map(|step| step.step.as_str()).collect();
+
+    sqlx::query("INSERT INTO recipe_search (rowid, title, summary, ingredients, instructions) VALUES (?, ?, ?, ?, ?)")
+        .bind(pk).bind(&recipe.title).bind(&recipe.summary).bind(ingredients.join("\n")).bind(instructions.join("\n"))
+        .execute(&mut **tx).await?;
+
+    Ok(())
+}
+
+async fn delete_children(tx: &mut Transaction<'_, Sqlite>, pk: i64) -> Result<(), sqlx::Error> {
+    for table in ["recipe_ingredients", "recipe_nutrients", "recipe_properties", "recipe_steps", "recipe_tags"] {
+        sqlx::query(&format!("DELETE FROM {} WHERE recipe_pk = ?", table)).bind(pk).execute(&mut **tx).await?;
+    }
+    sqlx::query("DELETE FROM recipe_search WHERE rowid = ?").bind(pk).execute(&mut **tx).await?;
+    Ok(())
+}
+
+async fn insert_recipe(tx: &mut Transaction<'_, Sqlite>, recipe: &Recipe) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query(
+        "INSERT INTO recipes (object_id, id, title, summary, image, vegetarian, vegan, gluten_free, dairy_free, ready_in_minutes, servings, calories, health_score, fingerprint, version, source) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 1, ?) RETURNING pk"
+    )
+        .bind(ObjectId::new().to_hex())
+        .bind(&recipe.id).bind(&recipe.title).bind(&recipe.summary).bind(&recipe.image)
+        .bind(recipe.vegetarian).bind(recipe.vegan).bind(recipe.gluten_free).bind(recipe.dairy_free)
+        .bind(recipe.ready_in_minutes).bind(recipe.servings)
+        .bind(recipe.calories().map(f64::from)).bind(recipe.health_score().map(f64::from))
+        .bind(recipe.fingerprint()).bind(recipe.source.name())
+        .fetch_one(&mut **tx).await?;
+
+    let pk: i64 = row.try_get("pk")?;
+    insert_children(tx, pk, recipe).await?;
+    Ok(pk)
+}
+
+// Overwrites the recipe stored under `id` if its version is one of `versions`, returning its key.
+async fn replace_recipe(tx: &mut Transaction<'_, Sqlite>, id: &str, recipe: &Recipe, versions: Option<&[i64]>) -> Result<Option<i64>, sqlx::Error> {
+    let mut query = QueryBuilder::<Sqlite>::new("UPDATE recipes SET ");
+    query.push("title = ").push_bind(&recipe.title)
+        .push(", summary = ").push_bind(&recipe.summary)
+        .push(", image = ").push_bind(&recipe.image)
+        .push(", vegetarian = ").push_bind(recipe.vegetarian)
+        .push(", vegan = ").push_bind(recipe.vegan)
+        .push(", gluten_free = ").push_bind(recipe.gluten_free)
+        .push(", dairy_free = ").push_bind(recipe.dairy_free)
+        .push(", ready_in_minutes = ").push_bind(recipe.ready_in_minutes)
+        .push(", servings = ").push_bind(recipe.servings)
+        .push(", calories = ").push_bind(recipe.calories().map(f64::from))
+        .push(", health_score = ").push_bind(recipe.health_score().map(f64::from))
+        .push(", fingerprint = ").push_bind(recipe.fingerprint())
+        .push(", version = version + 1 WHERE id = ").push_bind(id);
+
+    if let Some(versions) = versions {
+        query.push(" AND version IN ");
+        push_in(&mut query, versions);
+    }
+    query.push(" RETURNING pk");
+
+    let Some(row) = query.build().fetch_optional(&mut **tx).await? else {
+        return Ok(None);
+    };
+
+    let pk: i64 = row.try_get("pk")?;
+    delete_children(tx, pk).await?;
+    insert_children(tx, pk, recipe).await?;
+    Ok(Some(pk))
+}
+
+fn unique_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(error) if error.is_unique_violation())
+}
+
+#[async_trait]
+impl RecipeRepository for SqliteRecipeRepository {
+    async fn create(&self, recipe: &Recipe) -> Result<Recipe, RecipeError> {
+        let mut tx = self.pool.begin().await?;
+
+        let pk = insert_recipe(&mut tx, recipe).await.map_err(|e| {
+            if unique_violation(&e) { RecipeError::AlreadyExists(recipe.id.clone()) } else { e.into() }
+        })?;
+
+        tx.commit().await?;
+        self.load_one(pk).await
+    }
+
+    async fn upsert(&self, recipe: &Recipe) -> Result<(), RecipeError> {
+        let mut tx = self.pool.begin().await?;
+
+        let source: Option<String> = sqlx::query_scalar("SELECT source FROM recipes WHERE id = ?").bind(&recipe.id).fetch_optional(&mut *tx).await?;
+        if source.as_deref() == Some(RecipeSource::User.name()) {
+            return Err(RecipeError::AlreadyExists(recipe.id.clone()));
+        }
+
+        if replace_recipe(&mut tx, &recipe.id, recipe, None).await?.is_none() {
+            insert_recipe(&mut tx, recipe).await?;
+        }
+
+        sqlx::query("UPDATE recipes SET source = ? WHERE id = ?").bind(recipe.source.name()).bind(&recipe.id).execute(&mut *tx).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn read(&self, id: &str) -> Result<Option<Recipe>, RecipeError> {
+        let row = sqlx::query("SELECT pk FROM recipes WHERE id = ?").bind(id).fetch_optional(&self.pool).await?;
+
+        match row {
+            Some(row) => Ok(self.load(&[row.try_get("pk")?]).await?.pop()),
+            None => Ok(None)
+        }
+    }
+
+    async fn update(&self, id: &str, recipe: &Recipe, versions: Option<&[i64]>) -> Result<Recipe, RecipeError> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some(pk) = replace_recipe(&mut tx, id, recipe, versions).await? else {
+            return Err(self.missing(id, versions).await);
+        };
+
+        tx.commit().await?;
+        self.load_one(pk).await
+    }
+
+    // Rows are rewritten whole, so there is nothing to gain from the patch's individual paths here.
+    async fn patch(&self, id: &str, patched: &PatchedRecipe, version: i64) -> Result<Recipe, RecipeError> {
+        self.update(id, &patched.recipe, Some(&[version])).await
+    }
+
+    async fn delete(&self, id: &str, versions: Option<&[i64]>) -> Result<(), RecipeError> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut query = QueryBuilder::<Sqlite>::new("DELETE FROM recipes WHERE id = ");
+        query.push_bind(id);
+        if let Some(versions) = versions {
+            query.push(" AND version IN ");
+            push_in(&mut query, versions);
+        }
+        query.push(" RETURNING pk");
+
+        let Some(row) = query.build().fetch_optional(&mut *tx).await? else {
+            return Err(self.missing(id, versions).await);
+        };
+
+        sqlx::query("DELETE FROM recipe_search WHERE rowid = ?").bind(row.try_get::<i64, _>("pk")?).execute(&mut *tx).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn filter(&self, filters: Filters, sort: &[SortKey], page: usize, page_size: usize) -> Result<Page, RecipeError> {
+        let constraints = filter_builder::constraints(&filters);
+        let mut query = QueryBuilder::new("SELECT r.pk FROM recipes r");
+        push_filter(&mut query, &constraints);
+
+        query.push(" ORDER BY ");
+        if let (true, Some(expression)) = (sort.is_empty(), text_search(&constraints)) {
+            query.push("(SELECT bm25(recipe_search, 10.0, 2.0, 5.0, 1.0) FROM recipe_search WHERE recipe_search MATCH ")
+                .push_bind(expression)
+                .push(" AND rowid = r.pk), ");
+        }
+        for key in sort {
+            query.push(format!("{} {}, ", sort_column(key.field), if key.descending { "DESC" } else { "ASC" }));
+        }
+        query.push("r.object_id ASC LIMIT ").push_bind(page_size as i64).push(" OFFSET ").push_bind(((page - 1) * page_size) as i64);
+
+        let pks = self.pks(query).await?;
+
+        Ok(Page { recipes: self.load(&pks).await?, total_items: self.count(filters).await? })
+    }
+
+    async fn filter_keyset(&self, filters: Filters, cursor: Option<&Cursor>, page_size: usize) -> Result<KeysetPage, RecipeError> {
+        let direction = cursor.map_or(Direction::Next, |cursor| cursor.direction);
+
+        let mut query = QueryBuilder::new("SELECT r.pk FROM recipes r");
+        push_filter(&mut query, &filter_builder::constraints(&filters));
+
+        if let Some(cursor) = cursor {
+            let operator = match cursor.direction {
+                Direction::Next => " AND r.object_id > ",
+                Direction::Prev => " AND r.object_id < "
+            };
+            query.push(operator).push_bind(cursor.id.to_hex());
+        }
+
+        // ObjectId hex strings sort like the ids themselves.
+        query.push(match direction {
+            Direction::Next => " ORDER BY r.object_id ASC",
+            Direction::Prev => " ORDER BY r.object_id DESC"
+        });
+        query.push(" LIMIT ").push_bind(page_size as i64 + 1);
+
+        let mut pks = self.pks(query).await?;
+
+        let has_more = pks.len() > page_size;
+        pks.truncate(page_size);
+
+        if direction == Direction::Prev {
+            pks.reverse();
+        }
+
+        Ok(KeysetPage { recipes: self.load(&pks).await?, has_more })
+    }
+
+    async fn count(&self, filters: Filters) -> Result<u64, RecipeError> {
+        let mut query = QueryBuilder::new("SELECT COUNT(*) AS total FROM recipes r");
+        push_filter(&mut query, &filter_builder::constraints(&filters));
+
+        let row = query.build().fetch_one(&self.pool).await?;
+        Ok(row.try_get::<i64, _>("total")? as u64)
+    }
+
+    async fn find_by_ingredient_terms(&self, terms: &[String], limit: usize) -> Result<Vec<Recipe>, RecipeError> {
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // One joined row per matching ingredient, so the count is how many of the recipe's ingredients matched.
+        let mut query = QueryBuilder::new(
+            "SELECT r.pk, COUNT(*) AS matched, \
+             (SELECT COUNT(*) FROM recipe_ingredients a WHERE a.recipe_pk = r.pk) - COUNT(*) AS unmatched \
+             FROM recipes r JOIN recipe_ingredients i ON i.recipe_pk = r.pk WHERE "
+        );
+        for (index, term) in terms.iter().enumerate() {
+            if index > 0 {
+                query.push(" OR ");
+            }
+            push_word_prefix(&mut query, "i.name", term);
+        }
+        query.push(" GROUP BY r.pk ORDER BY unmatched ASC, matched DESC, r.object_id ASC LIMIT ").push_bind(limit as i64);
+
+        let pks = self.pks(query).await?;
+        self.load(&pks).await
+    }
+
+    async fn find_duplicates(&self, fingerprints: &[String]) -> Result<HashMap<String, String>, RecipeError> {
+        if fingerprints.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let rows = in_list("SELECT fingerprint, id FROM recipes WHERE fingerprint IN ", fingerprints, "").build().fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| Ok((row.try_get("fingerprint")?, row.try_get("id")?))).collect::<Result<_, sqlx::Error>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        auth::{ApiKeyStore, Scope, SqliteApiKeyStore},
+        sort::parse_sort,
+        test_support::{filter_cases, filters, fixture_recipes, TempDatabase}
+    };
+
+    // The fixture stored the way the import stores it.
+    async fn repository() -> (TempDatabase, SqliteRecipeRepository) {
+        let (database, pool) = TempDatabase::connect().await;
+        let repository = SqliteRecipeRepository::new(pool);
+
+        for recipe in fixture_recipes() {
+            repository.upsert(&recipe).await.unwrap();
+        }
+
+        (database, repository)
+    }
+
+    fn ids(recipes: &[Recipe]) -> Vec<&str> {
+        recipes.iter().map(|recipe| recipe.id.as_str()).collect()
+    }
+
+    async fn filtered(repository: &SqliteRecipeRepository, filters: Filters, sort: &str) -> Vec<String> {
+        let sort = if sort.is_empty() { Vec::new() } else { parse_sort(sort).unwrap() };
+        let page = repository.filter(filters, &sort, 1, 10).await.unwrap();
+        ids(&page.recipes).into_iter().map(str::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn filters_match_the_fixture() {
+        let (_database, repository) = repository().await;
+
+        for (value, expected) in filter_cases() {
+            assert_eq!(filtered(&repository, filters(value.clone()), "").await, expected, "{}", value);
+            assert_eq!(repository.count(filters(value.clone())).await.unwrap(), expected.len() as u64, "{}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn text_search_ranks_with_fts5_unless_sorted() {
+        let (_database, repository) = repository().await;
+
+        let by_relevance = filtered(&repository, filters(json!({ "query": "garlic basil" })), "").await;
+        assert_eq!(by_relevance[..2], ["715594", "716429"]);
+
+        let by_title = filtered(&repository, filters(json!({ "query": "garlic basil" })), "-title").await;
+        assert_eq!(by_title[..2], ["716429", "715594"]);
+
+        // Porter stemming finds the plural, and quoting keeps search syntax in the query from breaking it.
+        assert_eq!(filtered(&repository, filters(json!({ "query": "bean" })), "").await, ["782601"]);
+        assert!(filtered(&repository, filters(json!({ "query": "\"garlic AND -(" })), "").await.contains(&"716429".to_string()));
+    }
+
+    #[tokio::test]
+    async fn sorts_and_pages() {
+        let (_database, repository) = repository().await;
+
+        assert_eq!(filtered(&repository, Filters::default(), "-calories").await, ["716429", "795751", "782601", "715594"]);
+
+        let page = repository.filter(Filters::default(), &parse_sort("-calories").unwrap(), 2, 3).await.unwrap();
+        assert_eq!(page.total_items, 4);
+        assert_eq!(ids(&page.recipes), ["715594"]);
+    }
+
+    #[tokio::test]
+    async fn keyset_pages_move_both_ways() {
+        let (_database, repository) = repository().await;
+
+        let first = repository.filter_keyset(Filters::default(), None, 3).await.unwrap();
+        assert_eq!(ids(&first.recipes), ["716429", "715594", "782601"]);
+        assert!(first.has_more);
+
+        let next = Cursor { id: first.recipes[2]._id.unwrap(), direction: Direction::Next };
+        let second = repository.filter_keyset(Filters::default(), Some(&next), 3).await.unwrap();
+        assert_eq!(ids(&second.recipes), ["795751"]);
+        assert!(!second.has_more);
+
+        let prev = Cursor { id: second.recipes[0]._id.unwrap(), direction: Direction::Prev };
+        let back = repository.filter_keyset(Filters::default(), Some(&prev), 2).await.unwrap();
+        assert_eq!(ids(&back.recipes), ["715594", "782601"]);
+        assert!(back.has_more);
+    }
+
+    #[tokio::test]
+    async fn stale_version_is_told_apart_from_missing_recipe() {
+        let (_database, repository) = repository().await;
+        let recipe = repository.read("716429").await.unwrap().unwrap();
+
+        assert!(matches!(repository.update("716429", &recipe, Some(&[7])).await, Err(RecipeError::VersionMismatch(_))));
+        assert!(matches!(repository.update("missing", &recipe, Some(&[7])).await, Err(RecipeError::NotFound(_))));
+        assert!(matches!(repository.update("missing", &recipe, None).await, Err(RecipeError::NotFound(_))));
+        assert!(matches!(repository.delete("716429", Some(&[7])).await, Err(RecipeError::VersionMismatch(_))));
+        assert!(matches!(repository.delete("missing", Some(&[1])).await, Err(RecipeError::NotFound(_))));
+
+        let updated = repository.update("716429", &recipe, Some(&[recipe.version])).await.unwrap();
+        assert_eq!(updated.version, recipe.version + 1);
+    }
+
+    #[tokio::test]
+    async fn import_does_not_overwrite_user_recipes() {
+        let (_database, repository) = repository().await;
+        let mut recipes = fixture_recipes();
+
+        let mine = Recipe { id: "42".to_string(), title: "My Garlic Fries".to_string(), ..recipes.remove(1) }.into_user_recipe();
+        repository.create(&mine).await.unwrap();
+
+        let imported = Recipe { id: "42".to_string(), ..recipes.remove(0) };
+        assert!(matches!(repository.upsert(&imported).await, Err(RecipeError::AlreadyExists(_))));
+
+        let stored = repository.read("42").await.unwrap().unwrap();
+        assert_eq!(stored.title, "My Garlic Fries");
+        assert_eq!(stored.source, RecipeSource::User);
+
+        // Imported recipes are still refreshed in place.
+        let refreshed = Recipe { title: "Red Bean Jambalaya".to_string(), ..recipes.remove(0) };
+        repository.upsert(&refreshed).await.unwrap();
+        assert_eq!(repository.read("782601").await.unwrap().unwrap().version, 2);
+    }
+
+    #[tokio::test]
+    async fn search_index_follows_updates_and_deletes() {
+        let (_database, repository) = repository().await;
+        let search = |query: &str| filters(json!({ "query": query }));
+
+        let fries = repository.read("715594").await.unwrap().unwrap();
+        repository.update("715594", &Recipe { title: "Homemade Rosemary Chips".to_string(), ..fries }, None).await.unwrap();
+        assert_eq!(filtered(&repository, search("rosemary"), "").await, ["715594"]);
+
+        repository.delete("715594", None).await.unwrap();
+        assert!(filtered(&repository, search("rosemary"), "").await.is_empty());
+
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM recipe_search").fetch_one(&repository.pool).await.unwrap();
+        assert_eq!(rows, 3);
+    }
+
+    #[tokio::test]
+    async fn api_keys_round_trip() {
+        let (_database, pool) = TempDatabase::connect().await;
+        let store = SqliteApiKeyStore::new(pool);
+
+        let (raw_key, key) = store.create("ci", vec![Scope::RecipesRead, Scope::RecipesWrite], Some(Utc::now() + Duration::days(1))).await.unwrap();
+        let found = store.authenticate(&raw_key).await.unwrap().unwrap();
+        assert_eq!(found.id, key.id);
+        assert_eq!(found.scopes, [Scope::RecipesRead, Scope::RecipesWrite]);
+        assert_eq!(found.expires_at.map(|expires_at| expires_at.timestamp()), key.expires_at.map(|expires_at| expires_at.timestamp()));
+
+        assert!(store.revoke(&key.id).await.unwrap());
+        assert!(!store.revoke("missing").await.unwrap());
+        assert!(store.authenticate(&raw_key).await.unwrap().is_none());
+
+        let (expired, _) = store.create("old", vec![Scope::Admin], Some(Utc::now() - Duration::days(1))).await.unwrap();
+        assert!(store.authenticate(&expired).await.unwrap().is_none());
+    }
+}
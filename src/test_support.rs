@@ -0,0 +1,61 @@
+use std::{env, fs, path::PathBuf};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+
+use crate::{memory::InMemoryRecipeRepository, models::{Filters, Recipe}, sqlite};
+
+pub const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/recipes.json");
+
+pub fn repository() -> InMemoryRecipeRepository {
+    InMemoryRecipeRepository::from_fixture(FIXTURE).unwrap()
+}
+
+pub fn fixture_recipes() -> Vec<Recipe> {
+    serde_json::from_slice(&fs::read(FIXTURE).unwrap()).unwrap()
+}
+
+// A fixture recipe as raw JSON, the way a client would send it.
+pub fn fixture_json(id: &str) -> Value {
+    let recipes: Vec<Value> = serde_json::from_slice(&fs::read(FIXTURE).unwrap()).unwrap();
+    recipes.into_iter().find(|recipe| recipe["id"] == id).unwrap()
+}
+
+pub fn filters(value: Value) -> Filters {
+    serde_json::from_value(value).unwrap()
+}
+
+// Filters and the fixture recipes they select, in the order stored. Every backend must agree on these.
+pub fn filter_cases() -> Vec<(Value, &'static [&'static str])> {
+    vec![
+        (json!({ "diets": "vegan" }), &["715594", "782601"]),
+        (json!({ "intolerances": "dairy", "max_calories": 400 }), &["715594", "782601"]),
+        (json!({ "intolerances": "gluten", "max_fats": 15 }), &["715594", "782601"]),
+        (json!({ "intolerances": "dairy,peanut", "max_carbs": 50 }), &["715594"]),
+        (json!({ "nutrients": [{ "name": "Protein", "min": 30 }] }), &["795751"]),
+        (json!({ "nutrients": "Calories:350:450" }), &["782601", "795751"]),
+        (json!({ "cuisines": "mexican" }), &["795751"]),
+        (json!({ "min_servings": 3, "max_ready_time": 45 }), &["782601", "795751"]),
+        (json!({ "max_ready_time": 30 }), &[]),
+        (json!({ "query": "pep", "search_mode": "prefix" }), &["782601", "795751"]),
+        (json!({ "query": "garlic", "search_mode": "prefix", "dish_types": "side dish" }), &["715594"])
+    ]
+}
+
+// A SQLite database file of its own, removed again when dropped.
+pub struct TempDatabase {
+    path: PathBuf
+}
+
+impl TempDatabase {
+    pub async fn connect() -> (TempDatabase, SqlitePool) {
+        let path = env::temp_dir().join(format!("feastly-test-{:016x}.db", rand::random::<u64>()));
+        let pool = sqlite::connect(&format!("sqlite://{}", path.display())).await.unwrap();
+        (TempDatabase { path }, pool)
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use reqwest::Url;
+use serde::Serialize;
+
+use crate::{errors::AppError, models::Recipe};
+
+// Spoonacular's diet and cuisine vocabularies, compared case-insensitively.
+const DIETS: [&str; 16] = [
+    "gluten free", "ketogenic", "vegetarian", "lacto vegetarian", "ovo vegetarian", "lacto ovo vegetarian",
+    "vegan", "pescetarian", "pescatarian", "paleo", "paleolithic", "primal", "low fodmap", "fodmap friendly",
+    "whole 30", "dairy free"
+];
+
+const CUISINES: [&str; 27] = [
+    "african", "american", "asian", "british", "cajun", "caribbean", "chinese", "eastern european", "european",
+    "french", "german", "greek", "indian", "irish", "italian", "japanese", "jewish", "korean", "latin american",
+    "mediterranean", "mexican", "middle eastern", "nordic", "southern", "spanish", "thai", "vietnamese"
+];
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String
+}
+
+// Collects every failing rule instead of stopping at the first, so clients can fix a payload in one go.
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator::default()
+    }
+
+    pub fn error(&mut self, field: impl Into<String>, message: impl Into<String>) -> &mut Self {
+        self.errors.push(FieldError { field: field.into(), message: message.into() });
+        self
+    }
+
+    pub fn length(&mut self, field: impl Into<String>, value: &str, min: usize, max: usize) -> &mut Self {
+        let length = value.trim().chars().count();
+        if length < min || length > max {
+            self.error(field, format!("must be between {} and {} characters", min, max));
+        }
+        self
+    }
+
+    pub fn range<T: PartialOrd + std::fmt::Display>(&mut self, field: impl Into<String>, value: T, min: T, max: T) -> &mut Self {
+        if value < min || value > max {
+            self.error(field, format!("must be between {} and {}", min, max));
+        }
+        self
+    }
+
+    pub fn non_negative(&mut self, field: impl Into<String>, value: f32) -> &mut Self {
+        if !value.is_finite() || value < 0.0 {
+            self.error(field, "must be a non-negative number");
+        }
+        self
+    }
+
+    pub fn http_url(&mut self, field: impl Into<String>, value: &str) -> &mut Self {
+        let valid = Url::parse(value)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some());
+        if !valid {
+            self.error(field, "must be an absolute http(s) URL");
+        }
+        self
+    }
+
+    pub fn one_of(&mut self, field: &str, values: &[String], allowed: &[&str]) -> &mut Self {
+        for (index, value) in values.iter().enumerate() {
+            if !allowed.contains(&value.trim().to_lowercase().as_str()) {
+                self.error(format!("{}[{}]", field, index), format!("'{}' is not a recognised value", value));
+            }
+        }
+        self
+    }
+
+    pub fn finish(&mut self) -> Result<(), AppError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Unprocessable(std::mem::take(&mut self.errors)))
+        }
+    }
+}
+
+pub fn validate_recipe(recipe: &Recipe) -> Result<(), AppError> {
+    let mut validator = Validator::new();
+
+    if recipe._id.is_some() {
+        validator.error("_id", "is assigned by the server and must not be supplied");
+    }
+
+    validator
+        .length("id", &recipe.id, 1, 64)
+        .length("title", &recipe.title, 1, 200)
+        .length("summary", &recipe.summary, 0, 10_000)
+        .http_url("image", &recipe.image)
+        .range("ready_in_minutes", recipe.ready_in_minutes, 1, 7 * 24 * 60)
+        .range("servings", recipe.servings, 1, 100)
+        .one_of("diets", &recipe.diets, &DIETS)
+        .one_of("cuisines", &recipe.cuisines, &CUISINES);
+
+    if recipe.ingredients.is_empty() {
+        validator.error("ingredients", "must list at least one ingredient");
+    }
+
+    for (index, ingredient) in recipe.ingredients.iter().enumerate() {
+        validator
+            .length(format!("ingredients[{}].name", index), &ingredient.name, 1, 100)
+            .length(format!("ingredients[{}].unit", index), &ingredient.unit, 0, 32)
+            .non_negative(format!("ingredients[{}].amount", index), ingredient.amount);
+    }
+
+    for (index, nutrient) in recipe.nutrition.nutrients.iter().enumerate() {
+        validator
+            .length(format!("nutrition.nutrients[{}].name", index), &nutrient.name, 1, 100)
+            .non_negative(format!("nutrition.nutrients[{}].amount", index), nutrient.amount);
+    }
+
+    for (index, property) in recipe.nutrition.properties.iter().enumerate() {
+        validator
+            .length(format!("nutrition.properties[{}].name", index), &property.name, 1, 100)
+            .non_negative(format!("nutrition.properties[{}].amount", index), property.amount);
+    }
+
+    // Steps must be numbered 1, 2, 3, ... in order, without gaps or repeats.
+    let mut seen = HashSet::new();
+    for (index, step) in recipe.instructions.iter().enumerate() {
+        if !seen.insert(step.number) {
+            validator.error(format!("instructions[{}].number", index), format!("step {} appears more than once", step.number));
+        } else if step.number != index as i64 + 1 {
+            validator.error(format!("instructions[{}].number", index), format!("expected step {}", index + 1));
+        }
+
+        validator.length(format!("instructions[{}].step", index), &step.step, 1, 5_000);
+    }
+
+    validator.finish()
+}
+
+// A full replacement may leave the id out, taking it from the path, but may not change it.
+pub fn validate_replacement(id: &str, recipe: Recipe) -> Result<Recipe, AppError> {
+    if !recipe.id.is_empty() && recipe.id != id {
+        return Err(AppError::Unprocessable(vec![FieldError {
+            field: "id".to_string(),
+            message: format!("must match the recipe being replaced ({}) and cannot be changed", id)
+        }]));
+    }
+
+    let recipe = Recipe { id: id.to_string(), ..recipe };
+    validate_recipe(&recipe)?;
+    Ok(recipe)
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+
+    use super::*;
+    use crate::{models::Step, test_support::fixture_recipes};
+
+    fn recipe() -> Recipe {
+        fixture_recipes().into_iter().find(|recipe| recipe.id == "716429").unwrap()
+    }
+
+    fn errors(result: Result<impl Sized, AppError>) -> Vec<(String, String)> {
+        match result {
+            Err(AppError::Unprocessable(errors)) => errors.into_iter().map(|error| (error.field, error.message)).collect(),
+            Err(e) => panic!("expected a 422, got {}", e),
+            Ok(_) => panic!("expected a 422")
+        }
+    }
+
+    #[test]
+    fn fixture_recipes_are_valid() {
+        for recipe in fixture_recipes() {
+            assert!(validate_recipe(&recipe).is_ok(), "{}", recipe.id);
+        }
+    }
+
+    #[test]
+    fn reports_every_failing_field() {
+        let mut recipe = recipe();
+        recipe._id = Some(ObjectId::new());
+        recipe.title = "  ".to_string();
+        recipe.image = "ftp://example.com/pasta.jpg".to_string();
+        recipe.servings = 0;
+        recipe.diets = vec!["Vegan".to_string(), "carnivore".to_string()];
+        recipe.ingredients[1].amount = -2.0;
+        recipe.nutrition.nutrients[0].amount = f32::NAN;
+        recipe.instructions[1].number = 1;
+        recipe.instructions[2].step = String::new();
+
+        let fields: Vec<String> = errors(validate_recipe(&recipe)).into_iter().map(|(field, _)| field).collect();
+        assert_eq!(fields, [
+            "_id",
+            "title",
+            "image",
+            "servings",
+            "diets[1]",
+            "ingredients[1].amount",
+            "nutrition.nutrients[0].amount",
+            "instructions[1].number",
+            "instructions[2].step"
+        ]);
+    }
+
+    #[test]
+    fn steps_are_numbered_in_order() {
+        let step = |number: i64| Step { number, step: "Stir.".to_string() };
+        let mut recipe = recipe();
+
+        recipe.instructions = vec![step(1), step(3), step(3)];
+        assert_eq!(errors(validate_recipe(&recipe)), [
+            ("instructions[1].number".to_string(), "expected step 2".to_string()),
+            ("instructions[2].number".to_string(), "step 3 appears more than once".to_string())
+        ]);
+
+        recipe.instructions = Vec::new();
+        recipe.ingredients = Vec::new();
+        assert_eq!(errors(validate_recipe(&recipe)), [("ingredients".to_string(), "must list at least one ingredient".to_string())]);
+    }
+
+    #[test]
+    fn replacement_takes_its_id_from_the_path() {
+        let recipe = Recipe { id: String::new(), ..recipe() };
+        assert_eq!(validate_replacement("716429", recipe.clone()).ok().unwrap().id, "716429");
+
+        let recipe = Recipe { id: "other".to_string(), ..recipe };
+        assert_eq!(errors(validate_replacement("716429", recipe))[0].0, "id");
+    }
+}